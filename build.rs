@@ -6,9 +6,21 @@ use std::path::PathBuf;
 
 const GL_BINDINGS_FILE: &str = "gl_bindings.rs";
 const GLX_BINDINGS_FILE: &str = "glx_bindings.rs";
+const EGL_BINDINGS_FILE: &str = "egl_bindings.rs";
 const XLIB_BINDINGS_FILE: &str = "xlib_bindings.rs";
 
-const GLX_EXTENSIONS: [&str; 2] = ["GLX_EXT_texture_from_pixmap", "GLX_ARB_create_context"];
+const GLX_EXTENSIONS: [&str; 4] = [
+    "GLX_EXT_texture_from_pixmap",
+    "GLX_ARB_create_context",
+    "GLX_EXT_swap_control",
+    "GLX_EXT_buffer_age",
+];
+
+const EGL_EXTENSIONS: [&str; 3] = [
+    "EGL_KHR_platform_x11",
+    "EGL_KHR_image_pixmap",
+    "EGL_EXT_image_dma_buf_import",
+];
 
 const XLIB_FUNCTIONS: [&str; 5] = [
     "XOpenDisplay",
@@ -24,6 +36,10 @@ fn main() {
     println!("cargo:rustc-link-lib=X11");
     println!("cargo:rustc-link-lib=X11-xcb");
     println!("cargo:rustc-link-lib=GL");
+    let egl = env::var_os("CARGO_FEATURE_EGL").is_some();
+    if egl {
+        println!("cargo:rustc-link-lib=EGL");
+    }
 
     let mut builder = bindgen::Builder::default().header("xlib_libs.h");
     for function in XLIB_FUNCTIONS {
@@ -39,13 +55,13 @@ fn main() {
         .expect("couldn't write bindings!");
 
     let dest = env::var("OUT_DIR").unwrap();
-    let mut file = File::create(&Path::new(&dest).join(GL_BINDINGS_FILE)).unwrap();
+    let mut file = File::create(Path::new(&dest).join(GL_BINDINGS_FILE)).unwrap();
     Registry::new(Api::Gl, (3, 3), Profile::Core, Fallbacks::All, [])
         .write_bindings(GlobalGenerator, &mut file)
         .unwrap();
 
     let dest = env::var("OUT_DIR").unwrap();
-    let mut file = File::create(&Path::new(&dest).join(GLX_BINDINGS_FILE)).unwrap();
+    let mut file = File::create(Path::new(&dest).join(GLX_BINDINGS_FILE)).unwrap();
     Registry::new(
         Api::Glx,
         (1, 4),
@@ -55,4 +71,18 @@ fn main() {
     )
     .write_bindings(StaticGenerator, &mut file)
     .unwrap();
+
+    if egl {
+        let dest = env::var("OUT_DIR").unwrap();
+        let mut file = File::create(Path::new(&dest).join(EGL_BINDINGS_FILE)).unwrap();
+        Registry::new(
+            Api::Egl,
+            (1, 5),
+            Profile::Core,
+            Fallbacks::All,
+            EGL_EXTENSIONS,
+        )
+        .write_bindings(StaticGenerator, &mut file)
+        .unwrap();
+    }
 }
@@ -0,0 +1,29 @@
+#!/bin/sh
+# checks the d-bus interface on a private bus, e.g. in ci
+# needs Xvfb, dbus-run-session and dbus-send, and a build with `cargo build --features dbus`
+set -eu
+
+if [ -z "${XCOMPRS_TEST_BUS:-}" ]; then
+    XCOMPRS_TEST_BUS=1 exec dbus-run-session -- "$0" "$@"
+fi
+
+COMP_PID=
+Xvfb :2 -screen 0 800x600x24 &
+XVFB_PID=$!
+trap 'kill $XVFB_PID $COMP_PID 2>/dev/null || true' EXIT
+sleep 1
+DISPLAY=:2 ./target/debug/xcomprs --headless --dbus --backend xrender --socket /tmp/xcomprs-dbus-test.sock &
+COMP_PID=$!
+sleep 2
+# both exit straight away if they can't start
+kill -0 $XVFB_PID
+kill -0 $COMP_PID
+
+call() {
+    method=$1
+    shift
+    dbus-send --session --print-reply --dest=com.github.chjj.compton._2 / "com.github.chjj.compton.$method" "$@"
+}
+call list_win
+call opts_get string:backend
+call opts_set string:blur_background boolean:false
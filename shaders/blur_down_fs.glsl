@@ -0,0 +1,19 @@
+#version 330 core
+out vec4 frag_color;
+
+in vec2 tex_coord;
+
+uniform sampler2D blur_texture;
+// half the size of a pixel of the target, in uv space
+uniform vec2 half_pixel;
+uniform float offset;
+
+// dual kawase downsample: center sample weighted 4, plus the 4 diagonals
+void main() {
+  vec4 sum = texture(blur_texture, tex_coord) * 4.0;
+  sum += texture(blur_texture, tex_coord - half_pixel * offset);
+  sum += texture(blur_texture, tex_coord + half_pixel * offset);
+  sum += texture(blur_texture, tex_coord + vec2(half_pixel.x, -half_pixel.y) * offset);
+  sum += texture(blur_texture, tex_coord - vec2(half_pixel.x, -half_pixel.y) * offset);
+  frag_color = sum / 8.0;
+}
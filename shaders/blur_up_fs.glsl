@@ -0,0 +1,23 @@
+#version 330 core
+out vec4 frag_color;
+
+in vec2 tex_coord;
+
+uniform sampler2D blur_texture;
+// half the size of a pixel of the target, in uv space
+uniform vec2 half_pixel;
+uniform float offset;
+
+// dual kawase upsample: 4 edge samples weighted 1, 4 diagonal samples weighted 2
+void main() {
+  vec2 o = half_pixel * offset;
+  vec4 sum = texture(blur_texture, tex_coord + vec2(-o.x * 2.0, 0.0));
+  sum += texture(blur_texture, tex_coord + vec2(-o.x, o.y)) * 2.0;
+  sum += texture(blur_texture, tex_coord + vec2(0.0, o.y * 2.0));
+  sum += texture(blur_texture, tex_coord + vec2(o.x, o.y)) * 2.0;
+  sum += texture(blur_texture, tex_coord + vec2(o.x * 2.0, 0.0));
+  sum += texture(blur_texture, tex_coord + vec2(o.x, -o.y)) * 2.0;
+  sum += texture(blur_texture, tex_coord + vec2(0.0, -o.y * 2.0));
+  sum += texture(blur_texture, tex_coord + vec2(-o.x, -o.y)) * 2.0;
+  frag_color = sum / 12.0;
+}
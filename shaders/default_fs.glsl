@@ -2,15 +2,55 @@
 out vec4 frag_color;
 
 in vec2 tex_coord;
+flat in vec4 win_rect;
+// x: corner radius (0 for square corners), y: dim (0 leaves the window as is, 1 is black),
+// z: opacity (1 is opaque), w: 1 if bg_texture holds what's behind the window (when it's blurred),
+// otherwise bg_texture is not to be sampled and the window is blended over whatever is behind it
+flat in vec4 win_params;
+// x: which of win_texture holds the window, y: 1 if its first row is the top of the window,
+// which depends on the driver (GLX_Y_INVERTED_EXT)
+flat in vec2 win_texture_info;
 
-// texture samplers
-uniform sampler2D win_texture;
+// texture samplers, one for each window drawn together (BATCH_TEXTURES in gl_renderer.rs)
+#ifdef TEXTURE_RECTANGLE
+// the window is bound as GL_TEXTURE_RECTANGLE, which is sampled in pixels rather than from 0 to 1
+uniform sampler2DRect win_texture[8];
+#define SAMPLE_WIN(i, coord) texture(win_texture[i], (coord) * textureSize(win_texture[i]))
+#else
+uniform sampler2D win_texture[8];
+#define SAMPLE_WIN(i, coord) texture(win_texture[i], coord)
+#endif
 uniform sampler2D bg_texture;
 
 uniform vec2 screen_rect;
-uniform vec4 win_rect;
+
+// gl 3.3 can only index sampler arrays with constants
+vec4 sample_win(vec2 coord) {
+  switch (int(win_texture_info.x)) {
+    case 0: return SAMPLE_WIN(0, coord);
+    case 1: return SAMPLE_WIN(1, coord);
+    case 2: return SAMPLE_WIN(2, coord);
+    case 3: return SAMPLE_WIN(3, coord);
+    case 4: return SAMPLE_WIN(4, coord);
+    case 5: return SAMPLE_WIN(5, coord);
+    case 6: return SAMPLE_WIN(6, coord);
+    default: return SAMPLE_WIN(7, coord);
+  }
+}
+
+// signed distance from the edge of the rounded window, negative is inside
+float rounded_rect_dist(vec2 pos, vec2 size, float radius) {
+  vec2 half_size = size * 0.5;
+  vec2 q = abs(pos - half_size) - (half_size - radius);
+  return length(max(q, 0.0)) + min(max(q.x, q.y), 0.0) - radius;
+}
 
 void main() {
+  float corner_radius = win_params.x;
+  float dim = win_params.y;
+  float opacity = win_params.z;
+  float has_bg = win_params.w;
+
   float x = win_rect.x;
   float y = win_rect.y;
   float ww = win_rect.z;
@@ -30,7 +70,19 @@ void main() {
     y + tex_coord.y * wh
   );
 
-  // xorg pixmap is inverted for some reason
-  vec2 tex_coord_win = vec2(tex_coord.x, 1 - tex_coord.y);
-  frag_color = texture(win_texture, tex_coord_win) + 0.1 * texture(bg_texture, bg_tex_coord);
+  // gl textures start at the bottom, so a top to bottom pixmap has to be flipped
+  vec2 tex_coord_win = win_texture_info.y > 0.5 ? vec2(tex_coord.x, 1 - tex_coord.y) : tex_coord;
+  vec4 win = sample_win(tex_coord_win);
+  win.rgb *= 1.0 - dim;
+  win *= opacity;
+  // window colors are premultiplied, so translucent parts let the (possibly blurred) background through
+  vec4 color = win;
+  if (has_bg > 0.5) {
+    color += (1.0 - win.a) * vec4(texture(bg_texture, bg_tex_coord).rgb, 1.0);
+  }
+
+  // anti-aliased over about one pixel, output is blended on top of the target
+  float dist = rounded_rect_dist(tex_coord * win_rect.zw, win_rect.zw, corner_radius);
+  float mask = clamp(0.5 - dist, 0.0, 1.0);
+  frag_color = color * mask;
 }
\ No newline at end of file
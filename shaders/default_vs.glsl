@@ -1,26 +1,31 @@
 #version 330 core
 layout(location = 0) in vec2 pos;
 layout(location = 1) in vec2 tex_coord_in;
+// per instance attributes, an instance is a window or one of its visible rects
+// x, y, width, height of the whole window
+layout(location = 2) in vec4 win_rect_in;
+// the part of the window drawn, in the same space as tex_coord: x, y, width, height
+layout(location = 3) in vec4 uv_in;
+// x: corner radius, y: dim, z: opacity, w: has background (see default_fs.glsl)
+layout(location = 4) in vec4 win_params_in;
+// x: which of win_texture to sample, y: 1 if the texture is y inverted (see default_fs.glsl)
+layout(location = 5) in vec2 win_texture_in;
 
 uniform vec2 screen_rect;
-uniform vec4 win_rect;
 
 out vec2 tex_coord;
+flat out vec4 win_rect;
+flat out vec4 win_params;
+flat out vec2 win_texture_info;
 
 void main() {
   float w = screen_rect.x;
   float h = screen_rect.y;
-  // ndc_t = normalized device coordinate transform
-  // the translation and scale for our transform matrix
-  vec4 ndc_t = vec4(
-    2.0*win_rect.x/w - 1.0, - 2.0*win_rect.y/h,
-    2.0*win_rect.z/w, 2.0*win_rect.w/h
-  );
 
-  vec2 translate = vec2(2*win_rect.x/w - 1, 1 - 2*win_rect.y/h);
+  vec2 translate = vec2(2*win_rect_in.x/w - 1, 1 - 2*win_rect_in.y/h);
   mat4 transform = mat4(
-    2*win_rect.z/w, 0.0, 0.0, 0,
-    0.0, 2*win_rect.w/h, 0.0, 0,
+    2*win_rect_in.z/w, 0.0, 0.0, 0,
+    0.0, 2*win_rect_in.w/h, 0.0, 0,
     0.0, 0.0, 1, 0,
     0.0, 0.0, 0.0, 1
   ) * mat4(
@@ -30,6 +35,11 @@ void main() {
     0.0, 0.0, 0.0, 1
   );
 
-  gl_Position = vec4(pos, 0.0, 1.0)*transform;
-  tex_coord = tex_coord_in;
+  // pos goes from 0 to -1 down the window while the texture goes from 1 to 0
+  vec2 part_pos = vec2(uv_in.x + pos.x*uv_in.z, uv_in.y + (pos.y + 1)*uv_in.w - 1);
+  gl_Position = vec4(part_pos, 0.0, 1.0)*transform;
+  tex_coord = uv_in.xy + tex_coord_in*uv_in.zw;
+  win_rect = win_rect_in;
+  win_params = win_params_in;
+  win_texture_info = win_texture_in;
 }
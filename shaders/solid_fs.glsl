@@ -0,0 +1,7 @@
+#version 330 core
+out vec4 frag_color;
+
+// premultiplied
+uniform vec4 color;
+
+void main() { frag_color = color; }
@@ -0,0 +1,111 @@
+// talks to a running xcomprs over its control socket
+//
+// usage: xcomprs-msg [--socket <path>] <command> [args...]
+//   list-windows
+//   set-window <id> [opacity=<0-1>] [shader=<fragment shader path, empty for the default>]
+//   set-effect <blur|rounded-corners|inactive-dim|hud|debug-damage|debug-boxes> <on|off>
+//   reload-config
+//   reload-shaders
+//   screenshot <screen|monitor:N|window:ID> <path>
+//   stats
+//   --json '<request>'   send a raw request
+
+#[path = "../socket_path.rs"]
+mod socket_path;
+
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::UnixStream;
+
+use serde_json::{json, Value};
+
+fn main() {
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+    let mut socket = socket_path::default_socket_path();
+    if args.first().map(|a| a.as_str()) == Some("--socket") {
+        if args.len() < 2 {
+            fail("--socket needs a path");
+        }
+        socket = args[1].clone().into();
+        args.drain(..2);
+    }
+    let request = match build_request(&args) {
+        Ok(r) => r,
+        Err(e) => fail(&e),
+    };
+
+    let mut stream = match UnixStream::connect(&socket) {
+        Ok(s) => s,
+        Err(e) => fail(&format!("unable to connect to {}: {}", socket.display(), e)),
+    };
+    if let Err(e) = writeln!(stream, "{}", request) {
+        fail(&format!("unable to send request: {}", e));
+    }
+    let mut reply = String::new();
+    if let Err(e) = BufReader::new(stream).read_line(&mut reply) {
+        fail(&format!("unable to read reply: {}", e));
+    }
+    let reply: Value = match serde_json::from_str(&reply) {
+        Ok(v) => v,
+        Err(e) => fail(&format!("bad reply '{}': {}", reply.trim(), e)),
+    };
+    println!(
+        "{}",
+        serde_json::to_string_pretty(&reply).unwrap_or(reply.to_string())
+    );
+    if reply["ok"] != json!(true) {
+        std::process::exit(1);
+    }
+}
+
+fn build_request(args: &[String]) -> Result<Value, String> {
+    let command = args.first().ok_or("no command given".to_string())?;
+    let arg = |i: usize| {
+        args.get(i)
+            .ok_or(format!("'{}' is missing an argument", command))
+    };
+    match command.as_str() {
+        "--json" => serde_json::from_str(arg(1)?).map_err(|e| format!("invalid json: {}", e)),
+        "list-windows" | "reload-config" | "reload-shaders" | "stats" => {
+            Ok(json!({ "command": command }))
+        }
+        "set-window" => {
+            let mut ret = json!({ "command": command, "window": parse_window(arg(1)?)? });
+            for setting in &args[2..] {
+                match setting.split_once('=') {
+                    Some(("opacity", v)) => {
+                        let opacity: f32 =
+                            v.parse().map_err(|_| format!("invalid opacity '{}'", v))?;
+                        ret["opacity"] = json!(opacity);
+                    }
+                    Some(("shader", v)) => ret["shader"] = json!(v),
+                    _ => Err(format!("unknown window setting '{}'", setting))?,
+                }
+            }
+            Ok(ret)
+        }
+        "set-effect" => {
+            let enabled = match arg(2)?.as_str() {
+                "on" | "true" | "yes" => true,
+                "off" | "false" | "no" => false,
+                s => Err(format!("expected on or off, got '{}'", s))?,
+            };
+            Ok(json!({ "command": command, "effect": arg(1)?, "enabled": enabled }))
+        }
+        "screenshot" => Ok(json!({ "command": command, "target": arg(1)?, "path": arg(2)? })),
+        _ => Err(format!("unknown command '{}'", command)),
+    }
+}
+
+/// decimal or 0x prefixed hex, like xwininfo prints
+fn parse_window(s: &str) -> Result<u32, String> {
+    match s.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => s.parse(),
+    }
+    .map_err(|_| format!("invalid window id '{}'", s))
+}
+
+fn fail(msg: &str) -> ! {
+    eprintln!("xcomprs-msg: {}", msg);
+    std::process::exit(1);
+}
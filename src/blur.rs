@@ -0,0 +1,241 @@
+use crate::errors;
+use crate::gl;
+use crate::gl_renderer::{create_shader, gen_framebuffer, FboTexture, GpuMesh};
+use crate::win;
+
+use std::ffi::CString;
+
+const BLUR_TEXTURE_UNIFORM_NAME: &'static str = "blur_texture";
+const HALF_PIXEL_UNIFORM_NAME: &'static str = "half_pixel";
+const OFFSET_UNIFORM_NAME: &'static str = "offset";
+
+#[derive(Debug)]
+struct BlurShader {
+    program: gl::types::GLuint,
+    blur_texture_uniform_handle: gl::types::GLint,
+    half_pixel_uniform_handle: gl::types::GLint,
+    offset_uniform_handle: gl::types::GLint,
+}
+
+impl BlurShader {
+    unsafe fn new(vs_source: &String, fs_source: &String) -> Result<BlurShader, errors::CompError> {
+        let program = create_shader(
+            CString::new(vs_source.as_bytes())?,
+            CString::new(fs_source.as_bytes())?,
+        )?;
+        Ok(BlurShader {
+            program,
+            blur_texture_uniform_handle: get_uniform(program, BLUR_TEXTURE_UNIFORM_NAME)?,
+            half_pixel_uniform_handle: get_uniform(program, HALF_PIXEL_UNIFORM_NAME)?,
+            offset_uniform_handle: get_uniform(program, OFFSET_UNIFORM_NAME)?,
+        })
+    }
+}
+
+unsafe fn get_uniform(
+    program: gl::types::GLuint,
+    name: &str,
+) -> Result<gl::types::GLint, errors::CompError> {
+    let ret = gl::GetUniformLocation(program, CString::new(name)?.as_ptr());
+    if ret < 0 {
+        Err(format!(
+            "the blur shader does not define or does not use '{}'",
+            name
+        ))?
+    }
+    Ok(ret)
+}
+
+#[derive(Debug)]
+struct BlurLevel {
+    fbo: FboTexture,
+    width: u16,
+    height: u16,
+}
+
+/// dual kawase blur: downsample the source a few times, then upsample back to full size
+/// each pass samples a few texels around the current one so the blur gets wider every level
+#[derive(Debug)]
+pub struct KawaseBlur {
+    down: BlurShader,
+    up: BlurShader,
+
+    /// level i is 1/2^(i+1) the size of the screen
+    levels: Vec<BlurLevel>,
+    /// full size result of the last blur, only valid inside the last blurred region
+    pub output: FboTexture,
+
+    passes: u32,
+    width: u16,
+    height: u16,
+}
+
+impl KawaseBlur {
+    pub fn new_shader_paths(
+        vs_path: &str,
+        down_fs_path: &str,
+        up_fs_path: &str,
+        screen_width: u16,
+        screen_height: u16,
+        passes: u32,
+    ) -> Result<KawaseBlur, errors::CompError> {
+        KawaseBlur::new(
+            &std::fs::read_to_string(vs_path)?,
+            &std::fs::read_to_string(down_fs_path)?,
+            &std::fs::read_to_string(up_fs_path)?,
+            screen_width,
+            screen_height,
+            passes,
+        )
+    }
+    pub fn new(
+        vs_source: &String,
+        down_fs_source: &String,
+        up_fs_source: &String,
+        screen_width: u16,
+        screen_height: u16,
+        passes: u32,
+    ) -> Result<KawaseBlur, errors::CompError> {
+        if passes == 0 {
+            Err("blur needs at least one pass".to_string())?
+        }
+        unsafe {
+            let mut ret = KawaseBlur {
+                down: BlurShader::new(vs_source, down_fs_source)?,
+                up: BlurShader::new(vs_source, up_fs_source)?,
+                levels: vec![],
+                output: gen_framebuffer(screen_width, screen_height)?,
+                passes,
+                width: screen_width,
+                height: screen_height,
+            };
+            ret.gen_levels()?;
+            Ok(ret)
+        }
+    }
+
+    unsafe fn gen_levels(&mut self) -> Result<(), errors::CompError> {
+        let (mut w, mut h) = (self.width, self.height);
+        for _ in 0..self.passes {
+            // anything past 1x1 would just be the same texture again
+            if w <= 1 && h <= 1 {
+                break;
+            }
+            w = std::cmp::max(w / 2, 1);
+            h = std::cmp::max(h / 2, 1);
+            self.levels.push(BlurLevel {
+                fbo: gen_framebuffer(w, h)?,
+                width: w,
+                height: h,
+            });
+        }
+        Ok(())
+    }
+
+    pub fn resize(
+        &mut self,
+        screen_width: u16,
+        screen_height: u16,
+    ) -> Result<(), errors::CompError> {
+        self.width = screen_width;
+        self.height = screen_height;
+        unsafe {
+            self.output.resize(screen_width, screen_height)?;
+            // the number of levels can change for tiny screens, so just start over
+            for level in self.levels.drain(..) {
+                level.fbo.delete();
+            }
+            self.gen_levels()?;
+        }
+        Ok(())
+    }
+
+    /// how far outside a rect the blur samples from
+    pub fn margin(&self, offset: f32) -> i32 {
+        // the samples spread out by roughly `offset` texels per level, so anything
+        // further than that from the window can't affect the result
+        (offset * (1u32 << self.levels.len()) as f32).ceil() as i32
+    }
+
+    /// blurs the region of `source` covered by `rect` into `self.output`
+    /// only `rect` grown by `margin` has to be valid in `source`
+    /// `quad` is a full screen quad, leaves the viewport/scissor reset to full screen
+    pub unsafe fn blur(&self, quad: &GpuMesh, source: &FboTexture, rect: &win::Rect, offset: f32) {
+        let margin = self.margin(offset);
+        let region = (
+            rect.x as i32 - margin,
+            // gl framebuffers have y going up
+            self.height as i32 - (rect.y as i32 + rect.height as i32) - margin,
+            rect.width as i32 + 2 * margin,
+            rect.height as i32 + 2 * margin,
+        );
+
+        gl::Enable(gl::SCISSOR_TEST);
+        gl::ActiveTexture(gl::TEXTURE0);
+
+        gl::UseProgram(self.down.program);
+        gl::Uniform1i(self.down.blur_texture_uniform_handle, 0);
+        gl::Uniform1f(self.down.offset_uniform_handle, offset);
+        let mut src = source;
+        for (i, level) in self.levels.iter().enumerate() {
+            self.draw_pass(
+                quad,
+                &self.down,
+                src,
+                &level.fbo,
+                level.width,
+                level.height,
+                region,
+                i + 1,
+            );
+            src = &level.fbo;
+        }
+
+        gl::UseProgram(self.up.program);
+        gl::Uniform1i(self.up.blur_texture_uniform_handle, 0);
+        gl::Uniform1f(self.up.offset_uniform_handle, offset);
+        for i in (0..self.levels.len()).rev() {
+            let (target, w, h) = match i {
+                0 => (&self.output, self.width, self.height),
+                _ => (
+                    &self.levels[i - 1].fbo,
+                    self.levels[i - 1].width,
+                    self.levels[i - 1].height,
+                ),
+            };
+            self.draw_pass(quad, &self.up, &self.levels[i].fbo, target, w, h, region, i);
+        }
+
+        gl::Disable(gl::SCISSOR_TEST);
+        gl::Viewport(0, 0, self.width as i32, self.height as i32);
+    }
+
+    unsafe fn draw_pass(
+        &self,
+        quad: &GpuMesh,
+        shader: &BlurShader,
+        src: &FboTexture,
+        target: &FboTexture,
+        width: u16,
+        height: u16,
+        (x, y, w, h): (i32, i32, i32, i32),
+        level: usize,
+    ) {
+        gl::Uniform2f(
+            shader.half_pixel_uniform_handle,
+            0.5 / width as f32,
+            0.5 / height as f32,
+        );
+        gl::BindTexture(gl::TEXTURE_2D, src.texture);
+        gl::BindFramebuffer(gl::FRAMEBUFFER, target.fbo);
+        gl::Viewport(0, 0, width as i32, height as i32);
+        // round outwards so the region is fully covered at every level
+        gl::Scissor(
+            (x >> level) - 1,
+            (y >> level) - 1,
+            (w >> level) + 2,
+            (h >> level) + 2,
+        );
+        quad.draw();
+    }
+}
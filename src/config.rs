@@ -0,0 +1,438 @@
+use crate::errors;
+use crate::ewm;
+use crate::pacing;
+use crate::recorder;
+use crate::renderer;
+use crate::win;
+
+use x11rb::protocol::xproto::Window;
+
+/// path the config is read from when none is given on the command line
+pub const DEFAULT_CONFIG_PATH: &str = "./xcomprs.conf";
+
+#[derive(Debug, Clone)]
+pub struct BlurConfig {
+    /// number of downsample (and upsample) passes, each one halves the resolution
+    pub passes: u32,
+    /// how far apart (in texels) the kawase samples are taken
+    pub offset: f32,
+}
+
+impl BlurConfig {
+    /// how far outside a window the blur can sample from, the renderer may use fewer passes on small screens
+    pub fn margin(&self) -> i32 {
+        (self.offset * 2f32.powi(self.passes as i32)).ceil() as i32
+    }
+}
+
+impl Default for BlurConfig {
+    fn default() -> BlurConfig {
+        BlurConfig {
+            passes: 3,
+            offset: 2.5,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DimConfig {
+    /// how much inactive windows are darkened, between 0 (not at all) and 1 (black)
+    pub amount: f32,
+    /// seconds a fade from not dimmed at all to black would take when the focus changes,
+    /// so fading to `amount` takes `amount` times as long
+    pub fade_time: f32,
+}
+
+impl DimConfig {
+    /// how far a window's dim can move towards its target in `elapsed` seconds
+    pub fn max_step(&self, elapsed: f32) -> f32 {
+        match self.fade_time {
+            t if t > 0.0 => elapsed / t,
+            _ => f32::INFINITY,
+        }
+    }
+}
+
+impl Default for DimConfig {
+    fn default() -> DimConfig {
+        DimConfig {
+            amount: 0.0,
+            fade_time: 0.75,
+        }
+    }
+}
+
+/// switches for whole effects, so they can be turned off without touching the rules
+#[derive(Debug, Clone)]
+pub struct Effects {
+    pub blur: bool,
+    pub rounded: bool,
+    pub dim: bool,
+    /// frame timings drawn over everything, only by the gl renderer
+    pub hud: bool,
+    /// tint what was just repainted, fading out, for debugging (gl only)
+    pub debug_damage: bool,
+    /// outline every window with its stacking index (gl only)
+    pub debug_boxes: bool,
+}
+
+impl Effects {
+    /// sets the effect called `name` (as in the config keys, e.g. "blur")
+    pub fn set(&mut self, name: &str, enabled: bool) -> Result<(), errors::CompError> {
+        match name {
+            "blur" => self.blur = enabled,
+            "rounded-corners" => self.rounded = enabled,
+            "inactive-dim" => self.dim = enabled,
+            "hud" => self.hud = enabled,
+            "debug-damage" => self.debug_damage = enabled,
+            "debug-boxes" => self.debug_boxes = enabled,
+            _ => Err(format!(
+                "unknown effect '{}', expected blur, rounded-corners, inactive-dim, hud, \
+                 debug-damage or debug-boxes",
+                name
+            ))?,
+        }
+        Ok(())
+    }
+}
+
+impl Default for Effects {
+    fn default() -> Effects {
+        Effects {
+            blur: true,
+            rounded: true,
+            dim: true,
+            hud: false,
+            debug_damage: false,
+            debug_boxes: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Condition {
+    All,
+    Argb,
+    OverrideRedirect,
+    Dock,
+    Fullscreen,
+    Shaped,
+}
+
+impl Condition {
+    fn parse(s: &str) -> Result<Condition, errors::CompError> {
+        match s {
+            "all" => Ok(Condition::All),
+            "argb" => Ok(Condition::Argb),
+            "override-redirect" => Ok(Condition::OverrideRedirect),
+            "dock" => Ok(Condition::Dock),
+            "fullscreen" => Ok(Condition::Fullscreen),
+            "shaped" => Ok(Condition::Shaped),
+            _ => Err(format!("unknown rule condition '{}'", s))?,
+        }
+    }
+
+    pub fn matches(&self, win: &win::Win) -> bool {
+        match self {
+            Condition::All => true,
+            Condition::Argb => win.argb,
+            Condition::OverrideRedirect => win.override_redirect,
+            Condition::Dock => win.win_type == ewm::WinType::Dock,
+            Condition::Fullscreen => win.fullscreen,
+            Condition::Shaped => win.shaped,
+        }
+    }
+}
+
+/// a rule is a condition and the settings to apply to windows matching it
+/// e.g. `rule argb blur=true` or `rule !argb blur=false`
+#[derive(Debug, Clone)]
+pub struct Rule {
+    pub condition: Condition,
+    pub negate: bool,
+    pub blur: Option<bool>,
+    pub rounded: Option<bool>,
+}
+
+impl Rule {
+    fn parse(s: &str) -> Result<Rule, errors::CompError> {
+        let mut parts = s.split_whitespace();
+        let cond = parts
+            .next()
+            .ok_or("rule is missing a condition".to_string())?;
+        let (negate, cond) = match cond.strip_prefix('!') {
+            Some(c) => (true, c),
+            None => (false, cond),
+        };
+        let mut ret = Rule {
+            condition: Condition::parse(cond)?,
+            negate,
+            blur: None,
+            rounded: None,
+        };
+        for setting in parts {
+            let (key, val) = match setting.split_once('=') {
+                Some((k, v)) => (k, v),
+                // a bare key is shorthand for key=true
+                None => (setting, "true"),
+            };
+            match key {
+                "blur" => ret.blur = Some(parse_bool(val)?),
+                "rounded" => ret.rounded = Some(parse_bool(val)?),
+                _ => Err(format!("unknown rule setting '{}'", key))?,
+            }
+        }
+        Ok(ret)
+    }
+
+    pub fn matches(&self, win: &win::Win) -> bool {
+        self.condition.matches(win) != self.negate
+    }
+}
+
+/// the settings for a single window, after all matching rules have been applied
+#[derive(Debug, Clone, Default)]
+pub struct WinSettings {
+    pub blur: bool,
+    pub rounded: bool,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Config {
+    pub blur: BlurConfig,
+    /// radius (in pixels) of the corners of windows with rounded=true
+    pub corner_radius: f32,
+    pub dim: DimConfig,
+    pub effects: Effects,
+    pub pacing: pacing::PacingMode,
+    pub backend: renderer::BackendKind,
+    /// render offscreen without showing anything, frames can only be seen by reading them back
+    pub headless: bool,
+    /// write every frame here (as a PAM image), so tests can check what was composited
+    pub dump_frame: Option<String>,
+    /// where screenshots triggered by SIGUSR1 go
+    pub screenshot_dir: String,
+    pub record: recorder::RecordConfig,
+    /// control socket, `None` for the default in $XDG_RUNTIME_DIR
+    pub ipc_socket: Option<String>,
+    /// register on the session bus, needs the `dbus` feature
+    pub dbus: bool,
+    /// applied in order, later rules override earlier ones
+    pub rules: Vec<Rule>,
+}
+
+impl Config {
+    /// a missing file gives the default config
+    pub fn load_or_default(path: &str) -> Result<Config, errors::CompError> {
+        match std::fs::read_to_string(path) {
+            Ok(s) => Config::parse(&s),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Config::default()),
+            Err(e) => Err(e)?,
+        }
+    }
+
+    /// read `path` again, keeping what can only be set on startup from `self`
+    pub fn reload(&self, path: &str) -> Result<Config, errors::CompError> {
+        let mut ret = Config::load_or_default(path)?;
+        ret.backend = self.backend;
+        ret.headless = self.headless;
+        ret.dump_frame = self.dump_frame.clone();
+        ret.record = self.record.clone();
+        ret.ipc_socket = self.ipc_socket.clone();
+        ret.dbus = self.dbus;
+        // the blur framebuffers are made for a number of passes
+        ret.blur.passes = self.blur.passes;
+        Ok(ret)
+    }
+
+    /// config is line based, `key = value` or `rule <condition> <setting>=<value>...`
+    /// anything after a '#' is a comment
+    pub fn parse(source: &str) -> Result<Config, errors::CompError> {
+        let mut ret = Config::default();
+        for (num, line) in source.lines().enumerate() {
+            let line = match line.split_once('#') {
+                Some((l, _)) => l,
+                None => line,
+            }
+            .trim();
+            if line.is_empty() {
+                continue;
+            }
+            ret.parse_line(line)
+                .map_err(|e| format!("config line {}: {:?}", num + 1, e))?;
+        }
+        Ok(ret)
+    }
+
+    fn parse_line(&mut self, line: &str) -> Result<(), errors::CompError> {
+        if let Some(rule) = line.strip_prefix("rule ") {
+            self.rules.push(Rule::parse(rule)?);
+            return Ok(());
+        }
+        let (key, val) = line
+            .split_once('=')
+            .ok_or(format!("expected 'key = value', got '{}'", line))?;
+        let val = val.trim();
+        match key.trim() {
+            "blur-passes" => self.blur.passes = val.parse().map_err(|_| bad_value(key, val))?,
+            "blur-offset" => self.blur.offset = val.parse().map_err(|_| bad_value(key, val))?,
+            "corner-radius" => self.corner_radius = val.parse().map_err(|_| bad_value(key, val))?,
+            "inactive-dim" => self.dim.amount = val.parse().map_err(|_| bad_value(key, val))?,
+            "enable-blur" => self.effects.blur = parse_bool(val)?,
+            "enable-rounded-corners" => self.effects.rounded = parse_bool(val)?,
+            "enable-inactive-dim" => self.effects.dim = parse_bool(val)?,
+            "show-hud" => self.effects.hud = parse_bool(val)?,
+            "debug-damage" => self.effects.debug_damage = parse_bool(val)?,
+            "debug-boxes" => self.effects.debug_boxes = parse_bool(val)?,
+            "frame-pacing" => self.pacing = pacing::PacingMode::parse(val)?,
+            "backend" => self.backend = renderer::BackendKind::parse(val)?,
+            "headless" => self.headless = parse_bool(val)?,
+            "dump-frame" => self.dump_frame = Some(val.to_string()),
+            "screenshot-dir" => self.screenshot_dir = val.to_string(),
+            "record" => self.record.path = Some(val.to_string()),
+            "record-format" => self.record.format = recorder::RecordFormat::parse(val)?,
+            "record-fps" => self.record.fps = val.parse().map_err(|_| bad_value(key, val))?,
+            "ipc-socket" => self.ipc_socket = Some(val.to_string()),
+            "dbus" => self.dbus = parse_bool(val)?,
+            "inactive-dim-fade-time" => {
+                self.dim.fade_time = val.parse().map_err(|_| bad_value(key, val))?
+            }
+            _ => Err(format!("unknown config key '{}'", key.trim()))?,
+        }
+        Ok(())
+    }
+
+    pub fn settings_for(&self, win: &win::Win) -> WinSettings {
+        let mut ret = WinSettings::default();
+        for rule in self.rules.iter().filter(|r| r.matches(win)) {
+            if let Some(blur) = rule.blur {
+                ret.blur = blur;
+            }
+            if let Some(rounded) = rule.rounded {
+                ret.rounded = rounded;
+            }
+        }
+        ret.blur &= self.effects.blur;
+        ret.rounded &= self.effects.rounded;
+        ret
+    }
+
+    /// only normal windows are dimmed, docks/menus/etc. are left alone
+    /// `active` is the window from _NET_ACTIVE_WINDOW, which can be either the frame or the client
+    pub fn dim_target(&self, win: &win::Win, active: Option<Window>) -> f32 {
+        let is_active = active.is_some() && (active == Some(win.handle()) || active == win.client);
+        match (is_active, win.win_type, win.override_redirect) {
+            (false, ewm::WinType::Normal, false) if self.effects.dim => self.dim.amount,
+            _ => 0.0,
+        }
+    }
+}
+
+fn parse_bool(s: &str) -> Result<bool, errors::CompError> {
+    match s {
+        "true" | "yes" | "on" => Ok(true),
+        "false" | "no" | "off" => Ok(false),
+        _ => Err(format!("expected a boolean, got '{}'", s))?,
+    }
+}
+
+fn bad_value(key: &str, val: &str) -> String {
+    format!("invalid value for '{}': '{}'", key.trim(), val)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn win(depth: u8) -> win::Win {
+        win::Win::without_damage(1, win::Rect::new(0, 0, 100, 100), 0, false, 0, depth, true)
+    }
+
+    #[test]
+    fn example_config_parses() {
+        Config::parse(include_str!("../xcomprs.conf")).unwrap();
+    }
+
+    #[test]
+    fn parse_lines() {
+        let config = Config::parse(
+            "# comment\n\
+             \n\
+             blur-passes = 5 # trailing comment\n\
+             blur-offset=1.5\n\
+             corner-radius = 4.5\n\
+             frame-pacing = damage\n\
+             backend = xrender\n\
+             dump-frame = /tmp/frame.pam\n\
+             enable-blur = off\n\
+             ",
+        )
+        .unwrap();
+        assert_eq!(config.blur.passes, 5);
+        assert_eq!(config.blur.offset, 1.5);
+        assert_eq!(config.corner_radius, 4.5);
+        assert_eq!(config.pacing, pacing::PacingMode::MostDamage);
+        assert_eq!(config.backend, renderer::BackendKind::XRender);
+        assert_eq!(config.dump_frame.as_deref(), Some("/tmp/frame.pam"));
+        assert!(!config.effects.blur);
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert!(Config::parse("no-such-key = 1").is_err());
+        assert!(Config::parse("blur-passes = lots").is_err());
+        assert!(Config::parse("blur-passes").is_err());
+        assert!(Config::parse("rule").is_err());
+        assert!(Config::parse("rule transparent blur").is_err());
+        assert!(Config::parse("rule argb blur=maybe").is_err());
+        assert!(Config::parse("rule argb sparkle=true").is_err());
+    }
+
+    #[test]
+    fn parse_rules() {
+        let config = Config::parse("rule !argb blur=false rounded\n").unwrap();
+        let rule = &config.rules[0];
+        assert_eq!(rule.condition, Condition::Argb);
+        assert!(rule.negate);
+        assert_eq!(rule.blur, Some(false));
+        assert_eq!(rule.rounded, Some(true));
+    }
+
+    #[test]
+    fn rules_match() {
+        let (opaque, argb) = (win(24), win(32));
+        let config = Config::parse("rule argb blur\nrule !argb blur").unwrap();
+        assert!(config.rules[0].matches(&argb) && !config.rules[0].matches(&opaque));
+        assert!(config.rules[1].matches(&opaque) && !config.rules[1].matches(&argb));
+        assert!(Condition::All.matches(&opaque));
+
+        let mut dock = win(24);
+        dock.win_type = ewm::WinType::Dock;
+        assert!(Condition::Dock.matches(&dock));
+        assert!(!Condition::Dock.matches(&opaque));
+    }
+
+    #[test]
+    fn later_rules_override() {
+        let config = Config::parse(
+            "rule all blur\n\
+             rule argb blur=false\n\
+             rule all rounded\n\
+             ",
+        )
+        .unwrap();
+        let settings = config.settings_for(&win(24));
+        assert!(settings.blur);
+        assert!(settings.rounded);
+        let settings = config.settings_for(&win(32));
+        assert!(!settings.blur);
+        assert!(settings.rounded);
+    }
+
+    #[test]
+    fn disabled_effects_override_rules() {
+        let config = Config::parse("enable-blur = false\nrule all blur rounded").unwrap();
+        let settings = config.settings_for(&win(32));
+        assert!(!settings.blur && settings.rounded);
+    }
+}
@@ -0,0 +1,259 @@
+use crate::win::Rect;
+
+/// past this many rectangles they're merged into their bounding box, so unions stay cheap
+const MAX_RECTS: usize = 64;
+
+/// a union of rectangles in root coordinates, e.g. everything that changed since the last frame
+/// rectangles can overlap, but none is entirely inside another
+#[derive(Debug, Clone, Default)]
+pub struct DamageRegion {
+    rects: Vec<Rect>,
+}
+
+impl DamageRegion {
+    pub fn add(&mut self, rect: Rect) {
+        if rect.width == 0 || rect.height == 0 || self.rects.iter().any(|r| contains(r, &rect)) {
+            return;
+        }
+        self.rects.retain(|r| !contains(&rect, r));
+        self.rects.push(rect);
+        if self.rects.len() > MAX_RECTS {
+            if let Some(bounds) = self.bounds() {
+                self.rects = vec![bounds];
+            }
+        }
+    }
+
+    pub fn union(&mut self, other: &DamageRegion) {
+        for r in other.rects.iter() {
+            self.add(*r);
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.rects.clear();
+    }
+
+    /// cut `cut` out of the region, which leaves it made of rectangles that don't overlap
+    /// if they didn't before
+    pub fn subtract(&mut self, cut: &Rect) {
+        let mut ret = Vec::with_capacity(self.rects.len());
+        for r in self.rects.drain(..) {
+            let i = match r.intersect(cut) {
+                Some(i) => i,
+                None => {
+                    ret.push(r);
+                    continue;
+                }
+            };
+            let (right, bottom) = (r.x as i32 + r.width as i32, r.y as i32 + r.height as i32);
+            let (i_right, i_bottom) = (i.x as i32 + i.width as i32, i.y as i32 + i.height as i32);
+            // full width bands above and below the cut, then what's left and right of it
+            let pieces = [
+                (r.x as i32, r.y as i32, right, i.y as i32),
+                (r.x as i32, i_bottom, right, bottom),
+                (r.x as i32, i.y as i32, i.x as i32, i_bottom),
+                (i_right, i.y as i32, right, i_bottom),
+            ];
+            for &(left, top, right, bottom) in pieces.iter() {
+                if right > left && bottom > top {
+                    ret.push(Rect::new(
+                        left as i16,
+                        top as i16,
+                        (right - left) as u16,
+                        (bottom - top) as u16,
+                    ));
+                }
+            }
+        }
+        self.rects = ret;
+    }
+
+    /// keep only what's also in `other`, the rectangles stay apart if they were
+    pub fn intersect(&mut self, other: &DamageRegion) {
+        let mut ret = vec![];
+        for r in self.rects.iter() {
+            // `other` can overlap itself, so each piece has what's already taken cut out of it
+            let start = ret.len();
+            for piece in other.rects.iter().filter_map(|o| o.intersect(r)) {
+                let mut piece = DamageRegion { rects: vec![piece] };
+                for taken in ret[start..].iter() {
+                    piece.subtract(taken);
+                }
+                ret.extend(piece.rects);
+            }
+        }
+        self.rects = ret;
+    }
+
+    pub fn intersects(&self, rect: &Rect) -> bool {
+        self.rects.iter().any(|r| r.intersect(rect).is_some())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rects.is_empty()
+    }
+
+    pub fn rects(&self) -> &[Rect] {
+        &self.rects
+    }
+
+    pub fn bounds(&self) -> Option<Rect> {
+        let first = self.rects.first()?;
+        let (mut left, mut top) = (first.x as i32, first.y as i32);
+        let (mut right, mut bottom) = (left, top);
+        for r in self.rects.iter() {
+            left = left.min(r.x as i32);
+            top = top.min(r.y as i32);
+            right = right.max(r.x as i32 + r.width as i32);
+            bottom = bottom.max(r.y as i32 + r.height as i32);
+        }
+        Some(Rect::new(
+            left as i16,
+            top as i16,
+            (right - left) as u16,
+            (bottom - top) as u16,
+        ))
+    }
+
+    /// pixels covered, counting overlaps once
+    pub fn area(&self) -> u64 {
+        self.area_within(None)
+    }
+
+    /// pixels covered inside `clip`, e.g. a monitor
+    pub fn area_within(&self, clip: Option<&Rect>) -> u64 {
+        let rects: Vec<Rect> = match clip {
+            Some(c) => self.rects.iter().filter_map(|r| r.intersect(c)).collect(),
+            None => self.rects.clone(),
+        };
+        // sweep over the x edges, merging the y spans of whatever crosses each slab
+        let mut xs: Vec<i32> = rects
+            .iter()
+            .flat_map(|r| vec![r.x as i32, r.x as i32 + r.width as i32])
+            .collect();
+        xs.sort_unstable();
+        xs.dedup();
+        let mut ret = 0;
+        for slab in xs.windows(2) {
+            let mut spans: Vec<(i32, i32)> = rects
+                .iter()
+                .filter(|r| r.x as i32 <= slab[0] && r.x as i32 + r.width as i32 >= slab[1])
+                .map(|r| (r.y as i32, r.y as i32 + r.height as i32))
+                .collect();
+            spans.sort_unstable();
+            let mut covered = 0;
+            let mut end = i32::MIN;
+            for (top, bottom) in spans {
+                let top = top.max(end);
+                if bottom > top {
+                    covered += bottom - top;
+                    end = bottom;
+                }
+            }
+            ret += (slab[1] - slab[0]) as u64 * covered as u64;
+        }
+        ret
+    }
+}
+
+fn contains(outer: &Rect, inner: &Rect) -> bool {
+    outer.x <= inner.x
+        && outer.y <= inner.y
+        && outer.x as i32 + outer.width as i32 >= inner.x as i32 + inner.width as i32
+        && outer.y as i32 + outer.height as i32 >= inner.y as i32 + inner.height as i32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn region(rects: &[Rect]) -> DamageRegion {
+        let mut ret = DamageRegion::default();
+        for r in rects {
+            ret.add(*r);
+        }
+        ret
+    }
+
+    #[test]
+    fn add_skips_empty_and_contained() {
+        let mut d = region(&[Rect::new(0, 0, 100, 100)]);
+        d.add(Rect::new(10, 10, 20, 20));
+        d.add(Rect::new(50, 50, 0, 10));
+        assert_eq!(d.rects, &[Rect::new(0, 0, 100, 100)]);
+        // a bigger one replaces what it covers
+        d.add(Rect::new(-10, -10, 200, 200));
+        assert_eq!(d.rects, &[Rect::new(-10, -10, 200, 200)]);
+    }
+
+    #[test]
+    fn add_merges_past_max_rects() {
+        let mut d = DamageRegion::default();
+        for i in 0..=MAX_RECTS as i16 {
+            d.add(Rect::new(i * 10, 0, 5, 5));
+        }
+        assert_eq!(d.rects, &[Rect::new(0, 0, MAX_RECTS as u16 * 10 + 5, 5)]);
+    }
+
+    #[test]
+    fn bounds() {
+        let d = region(&[Rect::new(0, 0, 10, 10), Rect::new(20, 30, 10, 10)]);
+        assert_eq!(d.bounds(), Some(Rect::new(0, 0, 30, 40)));
+        assert_eq!(DamageRegion::default().bounds(), None);
+    }
+
+    #[test]
+    fn union_skips_contained() {
+        let mut d = region(&[Rect::new(0, 0, 10, 10)]);
+        d.union(&region(&[Rect::new(20, 30, 10, 10), Rect::new(2, 2, 5, 5)]));
+        assert_eq!(d.rects.len(), 2);
+    }
+
+    #[test]
+    fn area_counts_overlap_once() {
+        let d = region(&[Rect::new(0, 0, 10, 10), Rect::new(5, 5, 10, 10)]);
+        assert_eq!(d.area(), 175);
+        assert_eq!(d.area_within(Some(&Rect::new(0, 0, 10, 10))), 100);
+        assert_eq!(d.area_within(Some(&Rect::new(100, 100, 10, 10))), 0);
+    }
+
+    #[test]
+    fn subtract_leaves_the_rest() {
+        let mut d = region(&[Rect::new(0, 0, 30, 30)]);
+        d.subtract(&Rect::new(10, 10, 10, 10));
+        assert_eq!(d.rects.len(), 4);
+        assert_eq!(d.area(), 800);
+        assert!(!d.intersects(&Rect::new(10, 10, 10, 10)));
+
+        d.subtract(&Rect::new(-5, -5, 50, 50));
+        assert!(d.is_empty());
+    }
+
+    #[test]
+    fn subtract_outside_is_noop() {
+        let mut d = region(&[Rect::new(0, 0, 10, 10)]);
+        d.subtract(&Rect::new(10, 0, 10, 10));
+        assert_eq!(d.rects, &[Rect::new(0, 0, 10, 10)]);
+    }
+
+    #[test]
+    fn intersect_stays_disjoint() {
+        let mut d = region(&[Rect::new(0, 0, 20, 20)]);
+        // overlapping rects in `other` mustn't give overlapping pieces
+        d.intersect(&region(&[
+            Rect::new(10, 0, 20, 10),
+            Rect::new(0, 5, 15, 10),
+        ]));
+        assert_eq!(d.area(), 225);
+        let total: u64 = d
+            .rects
+            .iter()
+            .map(|r| r.width as u64 * r.height as u64)
+            .sum();
+        assert_eq!(total, d.area());
+
+        d.intersect(&DamageRegion::default());
+        assert!(d.is_empty());
+    }
+}
@@ -0,0 +1,350 @@
+use crate::errors;
+use crate::renderer;
+use crate::win;
+
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::time::Duration;
+
+use dbus::arg::RefArg;
+use dbus::blocking::Connection;
+use dbus::message::MessageType;
+use dbus::strings::{ErrorName, Interface, Member, Path};
+use dbus::Message;
+use x11rb::protocol::xproto::Window;
+
+/// the interface compton (and picom) expose, so tools written for them work with us
+const INTERFACE: &str = "com.github.chjj.compton";
+
+const INTROSPECTION: &str = r#"<!DOCTYPE node PUBLIC "-//freedesktop//DTD D-BUS Object Introspection 1.0//EN"
+ "http://www.freedesktop.org/standards/dbus/1.0/introspect.dtd">
+<node name="/">
+  <interface name="org.freedesktop.DBus.Introspectable">
+    <method name="Introspect"><arg name="data" type="s" direction="out"/></method>
+  </interface>
+  <interface name="org.freedesktop.DBus.Peer">
+    <method name="Ping"/>
+  </interface>
+  <interface name="com.github.chjj.compton">
+    <signal name="win_added"><arg name="wid" type="u"/></signal>
+    <signal name="win_destroyed"><arg name="wid" type="u"/></signal>
+    <signal name="win_mapped"><arg name="wid" type="u"/></signal>
+    <signal name="win_unmapped"><arg name="wid" type="u"/></signal>
+    <method name="list_win"><arg type="au" direction="out"/></method>
+    <method name="find_win"><arg name="target" type="s" direction="in"/><arg type="u" direction="out"/></method>
+    <method name="win_get"><arg name="wid" type="u" direction="in"/><arg name="target" type="s" direction="in"/></method>
+    <method name="win_set"><arg name="wid" type="u" direction="in"/><arg name="target" type="s" direction="in"/></method>
+    <method name="opts_get"><arg name="target" type="s" direction="in"/></method>
+    <method name="opts_set"><arg name="target" type="s" direction="in"/></method>
+    <method name="reset"/>
+    <method name="repaint"/>
+  </interface>
+</node>
+"#;
+
+/// the compton d-bus interface on the session bus, polled from the main loop like the control socket
+/// methods take and return plain values (not variants), the type depending on the target
+pub struct DbusService {
+    conn: Connection,
+    /// for reset, which reloads the config
+    config_path: String,
+    /// what every window looked like at the last poll (mapped or not), for the win_* signals
+    known: HashMap<Window, bool>,
+}
+
+impl std::fmt::Debug for DbusService {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("DbusService")
+            .field("config_path", &self.config_path)
+            .field("known", &self.known)
+            .finish()
+    }
+}
+
+/// the service name has the display in it, so there can be one compositor per display
+pub fn service_name() -> String {
+    let display: String = std::env::var("DISPLAY")
+        .unwrap_or_default()
+        .chars()
+        .map(|c| match c.is_ascii_alphanumeric() {
+            true => c,
+            false => '_',
+        })
+        .collect();
+    format!("{}.{}", INTERFACE, display)
+}
+
+impl DbusService {
+    pub fn new(
+        tracker: &win::WinTracker,
+        config_path: &str,
+    ) -> Result<DbusService, errors::CompError> {
+        let conn = Connection::new_session()
+            .map_err(|e| format!("unable to connect to the session bus: {}", e))?;
+        let name = service_name();
+        conn.request_name(name.as_str(), false, true, true)
+            .map_err(|e| format!("unable to take the name {}: {}", name, e))?;
+        println!("d-bus service: {}", name);
+        Ok(DbusService {
+            conn,
+            config_path: config_path.to_string(),
+            known: known_wins(tracker),
+        })
+    }
+
+    /// answer any method calls that came in, and signal windows that changed since the last poll
+    pub fn poll<C: x11rb::connection::Connection>(
+        &mut self,
+        tracker: &mut win::WinTracker,
+        backend: &mut dyn renderer::Backend<C>,
+    ) -> Result<(), errors::CompError> {
+        let channel = self.conn.channel();
+        channel
+            .read_write(Some(Duration::from_millis(0)))
+            .map_err(|_| "lost the d-bus connection".to_string())?;
+        while let Some(msg) = channel.pop_message() {
+            if msg.msg_type() != MessageType::MethodCall {
+                continue;
+            }
+            let reply = match self.handle(&msg, tracker, backend) {
+                Ok(reply) => reply,
+                Err((name, e)) => {
+                    msg.error(&ErrorName::from(name), &CString::new(e).unwrap_or_default())
+                }
+            };
+            channel
+                .send(reply)
+                .map_err(|_| "unable to send d-bus reply".to_string())?;
+        }
+
+        let known = known_wins(tracker);
+        for (&w, &mapped) in known.iter() {
+            match self.known.get(&w) {
+                None => {
+                    self.signal("win_added", w)?;
+                    if mapped {
+                        self.signal("win_mapped", w)?;
+                    }
+                }
+                Some(&was_mapped) if was_mapped != mapped => match mapped {
+                    true => self.signal("win_mapped", w)?,
+                    false => self.signal("win_unmapped", w)?,
+                },
+                _ => (),
+            }
+        }
+        for &w in self.known.keys().filter(|w| !known.contains_key(w)) {
+            self.signal("win_destroyed", w)?;
+        }
+        self.known = known;
+        channel.flush();
+        Ok(())
+    }
+
+    fn signal(&self, name: &'static str, w: Window) -> Result<(), errors::CompError> {
+        let msg = Message::signal(
+            &Path::from("/"),
+            &Interface::from(INTERFACE),
+            &Member::from(name),
+        )
+        .append1(w);
+        self.conn
+            .channel()
+            .send(msg)
+            .map_err(|_| format!("unable to send d-bus signal {}", name))?;
+        Ok(())
+    }
+
+    /// errors are the d-bus error name and a message
+    fn handle<C: x11rb::connection::Connection>(
+        &self,
+        msg: &Message,
+        tracker: &mut win::WinTracker,
+        backend: &mut dyn renderer::Backend<C>,
+    ) -> Result<Message, (&'static str, String)> {
+        let interface = msg.interface().map(|i| i.to_string()).unwrap_or_default();
+        let member = msg.member().map(|m| m.to_string()).unwrap_or_default();
+        let mut args = msg.iter_init();
+        // only used by methods that take arguments, so a missing one is an invalid argument
+        let mut target = || -> Result<String, (&'static str, String)> {
+            let ret: String = args.read().map_err(invalid_args)?;
+            Ok(ret)
+        };
+
+        match (interface.as_str(), member.as_str()) {
+            ("org.freedesktop.DBus.Introspectable", "Introspect") => {
+                Ok(msg.method_return().append1(INTROSPECTION))
+            }
+            ("org.freedesktop.DBus.Peer", "Ping") => Ok(msg.method_return()),
+            (INTERFACE, "list_win") => {
+                let wins: Vec<u32> = tracker.wins().iter().skip(1).map(|w| w.handle()).collect();
+                Ok(msg.method_return().append1(wins))
+            }
+            (INTERFACE, "find_win") => {
+                let found = match target()?.as_str() {
+                    "focused" => tracker.active().and_then(|a| find(tracker, a)),
+                    // the frame the client is in
+                    "client" => {
+                        let client: u32 = msg.read2::<&str, u32>().map_err(invalid_args)?.1;
+                        find(tracker, client)
+                    }
+                    t => Err(invalid_target(t))?,
+                };
+                // compton answers 0 if there's no such window
+                Ok(msg.method_return().append1(found.unwrap_or(0)))
+            }
+            (INTERFACE, "win_get") => {
+                let (handle, target) = msg.read2::<u32, &str>().map_err(invalid_args)?;
+                let w = tracker
+                    .wins()
+                    .iter()
+                    .find(|w| w.handle() == handle)
+                    .ok_or((
+                        "org.freedesktop.DBus.Error.InvalidArgs",
+                        format!("no window {:#x}", handle),
+                    ))?;
+                let reply = msg.method_return();
+                Ok(match target {
+                    "id" => reply.append1(w.handle()),
+                    "client_win" => reply.append1(w.client.unwrap_or(0)),
+                    "map_state" => reply.append1(w.mapped()),
+                    "x" => reply.append1(w.rect.x as i32),
+                    "y" => reply.append1(w.rect.y as i32),
+                    "width" => reply.append1(w.rect.width as u32),
+                    "height" => reply.append1(w.rect.height as u32),
+                    "override_redirect" => reply.append1(w.override_redirect),
+                    "argb" => reply.append1(w.argb),
+                    "fullscreen" => reply.append1(w.fullscreen),
+                    "opacity" => reply.append1(w.opacity as f64),
+                    "blur_background" => reply.append1(w.settings.blur),
+                    "rounded_corners" => reply.append1(w.settings.rounded),
+                    t => Err(invalid_target(t))?,
+                })
+            }
+            (INTERFACE, "win_set") => {
+                let (handle, target) = msg.read2::<u32, &str>().map_err(invalid_args)?;
+                let value = value_arg(msg)?;
+                let w = tracker.find_win_mut(handle).ok_or((
+                    "org.freedesktop.DBus.Error.InvalidArgs",
+                    format!("no window {:#x}", handle),
+                ))?;
+                match target {
+                    "opacity" => {
+                        let opacity = value.as_f64().ok_or(invalid_value(target))?;
+                        w.opacity = opacity.max(0.0).min(1.0) as f32;
+                    }
+                    t => Err(invalid_target(t))?,
+                }
+                let rect = w.screen_rect();
+                tracker.damage_rect(rect);
+                Ok(msg.method_return())
+            }
+            (INTERFACE, "opts_get") => {
+                let config = &tracker.config;
+                let reply = msg.method_return();
+                Ok(match target()?.as_str() {
+                    "version" => reply.append1(env!("CARGO_PKG_VERSION")),
+                    "pid" => reply.append1(std::process::id() as i32),
+                    "display" => reply.append1(std::env::var("DISPLAY").unwrap_or_default()),
+                    "config_file" => reply.append1(self.config_path.as_str()),
+                    "backend" => reply.append1(backend.name()),
+                    "paint_on_overlay" => reply.append1(true),
+                    "headless" => reply.append1(config.headless),
+                    "blur_background" => reply.append1(config.effects.blur),
+                    "rounded_corners" => reply.append1(config.effects.rounded),
+                    "corner_radius" => reply.append1(config.corner_radius as i32),
+                    "inactive_dim" => reply.append1(match config.effects.dim {
+                        true => config.dim.amount as f64,
+                        false => 0.0,
+                    }),
+                    "refresh_rate" => reply.append1(tracker.pacer.refresh_rate().round() as i32),
+                    t => Err(invalid_target(t))?,
+                })
+            }
+            (INTERFACE, "opts_set") => {
+                let target = target()?;
+                let value = value_arg(msg)?;
+                let config = &mut tracker.config;
+                match target.as_str() {
+                    "blur_background" => {
+                        config.effects.blur = value.as_i64().ok_or(invalid_value(&target))? != 0
+                    }
+                    "rounded_corners" => {
+                        config.effects.rounded = value.as_i64().ok_or(invalid_value(&target))? != 0
+                    }
+                    "corner_radius" => {
+                        config.corner_radius = value.as_f64().ok_or(invalid_value(&target))? as f32
+                    }
+                    "inactive_dim" => {
+                        config.dim.amount = value.as_f64().ok_or(invalid_value(&target))? as f32;
+                        config.effects.dim = true;
+                    }
+                    t => Err(invalid_target(t))?,
+                }
+                tracker.apply_rules();
+                Ok(msg.method_return())
+            }
+            (INTERFACE, "reset") => {
+                tracker.config = tracker
+                    .config
+                    .reload(&self.config_path)
+                    .map_err(|e| ("org.freedesktop.DBus.Error.Failed", format!("{:?}", e)))?;
+                tracker.apply_rules();
+                println!("config reloaded from {}", self.config_path);
+                Ok(msg.method_return())
+            }
+            (INTERFACE, "repaint") => {
+                tracker.damage_screen();
+                Ok(msg.method_return())
+            }
+            _ => Err((
+                "org.freedesktop.DBus.Error.UnknownMethod",
+                format!("unknown method {}.{}", interface, member),
+            )),
+        }
+    }
+}
+
+/// the frame `handle` is, or is the client of
+fn find(tracker: &win::WinTracker, handle: Window) -> Option<Window> {
+    tracker
+        .wins()
+        .iter()
+        .find(|w| w.handle() == handle || w.client == Some(handle))
+        .map(|w| w.handle())
+}
+
+fn known_wins(tracker: &win::WinTracker) -> HashMap<Window, bool> {
+    tracker
+        .wins()
+        .iter()
+        .skip(1)
+        .map(|w| (w.handle(), w.mapped()))
+        .collect()
+}
+
+/// the second argument of *_set, of whatever type the target takes
+fn value_arg(msg: &Message) -> Result<Box<dyn RefArg>, (&'static str, String)> {
+    let mut args = msg.iter_init();
+    // skip the window and/or target
+    let skip = match msg.member().as_deref() {
+        Some("win_set") => 2,
+        _ => 1,
+    };
+    for _ in 0..skip {
+        args.next();
+    }
+    args.get_refarg().ok_or(invalid_args("missing value"))
+}
+
+fn invalid_args(e: impl std::fmt::Display) -> (&'static str, String) {
+    ("org.freedesktop.DBus.Error.InvalidArgs", e.to_string())
+}
+
+fn invalid_target(target: &str) -> (&'static str, String) {
+    invalid_args(format!("unknown target '{}'", target))
+}
+
+fn invalid_value(target: &str) -> (&'static str, String) {
+    invalid_args(format!("wrong type of value for '{}'", target))
+}
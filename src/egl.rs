@@ -0,0 +1,21 @@
+#![allow(non_upper_case_globals)]
+#![allow(non_camel_case_types)]
+#![allow(non_snake_case)]
+#![allow(unused)]
+#![allow(deref_nullptr)]
+#![allow(bare_trait_objects)]
+
+// platform types the generated bindings expect to be defined by the includer (normally eglplatform.h)
+use std::os::raw;
+pub type khronos_utime_nanoseconds_t = khronos_uint64_t;
+pub type khronos_uint64_t = u64;
+pub type khronos_ssize_t = raw::c_long;
+pub type EGLint = i32;
+pub type EGLNativeDisplayType = *const raw::c_void;
+pub type EGLNativePixmapType = *const raw::c_void;
+pub type EGLNativeWindowType = *const raw::c_void;
+pub type NativeDisplayType = EGLNativeDisplayType;
+pub type NativePixmapType = EGLNativePixmapType;
+pub type NativeWindowType = EGLNativeWindowType;
+
+include!(concat!(env!("OUT_DIR"), "/egl_bindings.rs"));
@@ -0,0 +1,273 @@
+use crate::egl;
+use crate::errors;
+use crate::gl;
+use crate::win;
+use crate::xlib;
+
+use std::ffi::{c_void, CStr, CString};
+use std::os::raw::c_ulong;
+use std::os::unix::io::AsRawFd;
+use std::ptr::null;
+
+use x11rb::protocol::dri3::ConnectionExt as dri3_ConnectionExt;
+use x11rb::protocol::xproto::{Visualid, Window};
+
+/// glEGLImageTargetTexture2DOES, GL_OES_EGL_image isn't in the desktop gl registry so it's loaded by hand
+type ImageTargetTexture2DOES = unsafe extern "system" fn(gl::types::GLenum, *const c_void);
+
+// drm fourccs for the formats x uses for 24 and 32 bit windows
+const DRM_FORMAT_XRGB8888: u32 = 0x34325258;
+const DRM_FORMAT_ARGB8888: u32 = 0x34325241;
+
+#[rustfmt::skip]
+const CONFIG_ATTRS: [egl::types::EGLint; 11] = [
+    egl::SURFACE_TYPE as i32, egl::WINDOW_BIT as i32,
+    egl::RENDERABLE_TYPE as i32, egl::OPENGL_BIT as i32,
+    egl::RED_SIZE as i32, 8,
+    egl::GREEN_SIZE as i32, 8,
+    egl::BLUE_SIZE as i32, 8,
+    egl::NONE as i32,
+];
+
+#[rustfmt::skip]
+const CONTEXT_ATTRS: [egl::types::EGLint; 7] = [
+    egl::CONTEXT_MAJOR_VERSION as i32, 3,
+    egl::CONTEXT_MINOR_VERSION as i32, 3,
+    egl::CONTEXT_OPENGL_PROFILE_MASK as i32, egl::CONTEXT_OPENGL_CORE_PROFILE_BIT as i32,
+    egl::NONE as i32,
+];
+
+/// a gl context made with egl instead of glx, drawing to the overlay
+/// window pixmaps are imported as EGLImages, which don't need rebinding every frame like glx pixmaps
+#[derive(Debug)]
+pub struct EglContext {
+    display: egl::types::EGLDisplay,
+    surface: egl::types::EGLSurface,
+
+    /// the server can hand us the window buffers directly with dri3, so nothing gets copied
+    dma_buf: bool,
+    image_target_texture: ImageTargetTexture2DOES,
+}
+
+impl EglContext {
+    /// creates the context and makes it current, then loads the gl functions
+    pub unsafe fn new(
+        conn: &impl x11rb::connection::Connection,
+        display: *mut xlib::Display,
+        screen_num: usize,
+        overlay: Window,
+        visual: Visualid,
+    ) -> Result<EglContext, errors::CompError> {
+        let platform_attrs = [
+            egl::PLATFORM_X11_SCREEN_KHR as egl::types::EGLAttrib,
+            screen_num as egl::types::EGLAttrib,
+            egl::NONE as egl::types::EGLAttrib,
+        ];
+        let egl_display = egl::GetPlatformDisplay(
+            egl::PLATFORM_X11_KHR,
+            display as *mut c_void,
+            platform_attrs.as_ptr(),
+        );
+        if egl_display == egl::NO_DISPLAY {
+            Err("unable to get an egl display for the x display".to_string())?
+        }
+        let mut maj: i32 = 0;
+        let mut min: i32 = 0;
+        if egl::Initialize(egl_display, &mut maj, &mut min) == egl::FALSE as egl::types::EGLBoolean
+        {
+            Err(format!("unable to initialize egl: {:#x}", egl::GetError()))?
+        }
+        println!("EGL V{}.{}", maj, min);
+
+        let extensions = CStr::from_ptr(egl::QueryString(egl_display, egl::EXTENSIONS as i32))
+            .to_str()?
+            .split(' ')
+            .collect::<Vec<&str>>();
+        if !extensions.contains(&"EGL_KHR_image_pixmap") {
+            Err("missing egl extension 'EGL_KHR_image_pixmap'".to_string())?
+        }
+        let dma_buf = extensions.contains(&"EGL_EXT_image_dma_buf_import")
+            && conn.extension_information("DRI3")?.is_some();
+        if dma_buf {
+            // the server won't answer anything else until the version has been queried
+            conn.dri3_query_version(1, 0)?.reply()?;
+        }
+        println!("importing window pixmaps with dma_buf: {}", dma_buf);
+
+        if egl::BindAPI(egl::OPENGL_API) == egl::FALSE as egl::types::EGLBoolean {
+            Err("egl doesn't support desktop gl".to_string())?
+        }
+        let config = find_config(egl_display, visual)?;
+
+        let mut native_window = overlay as c_ulong;
+        let surface = egl::CreatePlatformWindowSurface(
+            egl_display,
+            config,
+            &mut native_window as *mut c_ulong as *mut c_void,
+            null(),
+        );
+        if surface == egl::NO_SURFACE {
+            Err(format!(
+                "unable to create egl surface for the overlay: {:#x}",
+                egl::GetError()
+            ))?
+        }
+        let context =
+            egl::CreateContext(egl_display, config, egl::NO_CONTEXT, CONTEXT_ATTRS.as_ptr());
+        if context == egl::NO_CONTEXT {
+            Err(format!(
+                "unable to create egl context: {:#x}",
+                egl::GetError()
+            ))?
+        }
+        if egl::MakeCurrent(egl_display, surface, surface, context)
+            == egl::FALSE as egl::types::EGLBoolean
+        {
+            Err(format!(
+                "unable to make egl context current: {:#x}",
+                egl::GetError()
+            ))?
+        }
+        // swaps wait for vblank, same as with glx
+        egl::SwapInterval(egl_display, 1);
+
+        gl::load_with(|s| {
+            let c_str = CString::new(s).unwrap();
+            egl::GetProcAddress(c_str.as_ptr()) as *const _
+        });
+        let image_target_texture =
+            egl::GetProcAddress(CString::new("glEGLImageTargetTexture2DOES")?.as_ptr())
+                as *const c_void;
+        if image_target_texture.is_null() {
+            Err("missing gl extension 'GL_OES_EGL_image'".to_string())?
+        }
+
+        Ok(EglContext {
+            display: egl_display,
+            surface,
+
+            dma_buf,
+            image_target_texture: std::mem::transmute(image_target_texture),
+        })
+    }
+
+    /// an image sharing the window's backing pixmap, must be destroyed when the pixmap changes
+    pub unsafe fn create_image(
+        &self,
+        win: &win::Win,
+        conn: &impl x11rb::connection::Connection,
+    ) -> Result<egl::types::EGLImage, errors::CompError> {
+        if self.dma_buf {
+            match self.import_dma_buf(win, conn) {
+                Ok(image) => return Ok(image),
+                Err(e) => println!(
+                    "warning: unable to import pixmap as dma_buf ({:?}), using the pixmap directly",
+                    e
+                ),
+            }
+        }
+        let attrs = [
+            egl::IMAGE_PRESERVED as egl::types::EGLAttrib,
+            egl::TRUE as egl::types::EGLAttrib,
+            egl::NONE as egl::types::EGLAttrib,
+        ];
+        let image = egl::CreateImage(
+            self.display,
+            egl::NO_CONTEXT,
+            egl::NATIVE_PIXMAP_KHR,
+            win.pixmap as usize as egl::types::EGLClientBuffer,
+            attrs.as_ptr(),
+        );
+        if image == egl::NO_IMAGE {
+            Err(format!(
+                "unable to create egl image from pixmap: {:#x}",
+                egl::GetError()
+            ))?
+        }
+        Ok(image)
+    }
+
+    unsafe fn import_dma_buf(
+        &self,
+        win: &win::Win,
+        conn: &impl x11rb::connection::Connection,
+    ) -> Result<egl::types::EGLImage, errors::CompError> {
+        let buffer = conn.dri3_buffer_from_pixmap(win.pixmap)?.reply()?;
+        let fourcc = match (buffer.depth, buffer.bpp) {
+            (24, 32) => DRM_FORMAT_XRGB8888,
+            (32, 32) => DRM_FORMAT_ARGB8888,
+            (depth, bpp) => Err(format!(
+                "no drm format for depth {} with {} bits per pixel",
+                depth, bpp
+            ))?,
+        };
+        #[rustfmt::skip]
+        let attrs = [
+            egl::WIDTH as egl::types::EGLAttrib, buffer.width as egl::types::EGLAttrib,
+            egl::HEIGHT as egl::types::EGLAttrib, buffer.height as egl::types::EGLAttrib,
+            egl::LINUX_DRM_FOURCC_EXT as egl::types::EGLAttrib, fourcc as egl::types::EGLAttrib,
+            egl::DMA_BUF_PLANE0_FD_EXT as egl::types::EGLAttrib, buffer.pixmap_fd.as_raw_fd() as egl::types::EGLAttrib,
+            egl::DMA_BUF_PLANE0_OFFSET_EXT as egl::types::EGLAttrib, 0,
+            egl::DMA_BUF_PLANE0_PITCH_EXT as egl::types::EGLAttrib, buffer.stride as egl::types::EGLAttrib,
+            egl::NONE as egl::types::EGLAttrib,
+        ];
+        // egl dups the fd, ours is closed when `buffer` is dropped
+        let image = egl::CreateImage(
+            self.display,
+            egl::NO_CONTEXT,
+            egl::LINUX_DMA_BUF_EXT,
+            null(),
+            attrs.as_ptr(),
+        );
+        if image == egl::NO_IMAGE {
+            Err(format!("egl error {:#x}", egl::GetError()))?
+        }
+        Ok(image)
+    }
+
+    pub unsafe fn destroy_image(&self, image: egl::types::EGLImage) {
+        egl::DestroyImage(self.display, image);
+    }
+
+    /// make `texture` show the contents of `image`
+    pub unsafe fn bind_image(&self, texture: gl::types::GLuint, image: egl::types::EGLImage) {
+        gl::BindTexture(gl::TEXTURE_2D, texture);
+        (self.image_target_texture)(gl::TEXTURE_2D, image);
+    }
+
+    pub unsafe fn swap_buffers(&self) {
+        egl::SwapBuffers(self.display, self.surface);
+    }
+}
+
+/// a config that can render to windows with `visual`, i.e. the overlay
+unsafe fn find_config(
+    display: egl::types::EGLDisplay,
+    visual: Visualid,
+) -> Result<egl::types::EGLConfig, errors::CompError> {
+    let mut configs: [egl::types::EGLConfig; 64] = [null(); 64];
+    let mut num_configs: i32 = 0;
+    if egl::ChooseConfig(
+        display,
+        CONFIG_ATTRS.as_ptr(),
+        configs.as_mut_ptr(),
+        configs.len() as i32,
+        &mut num_configs,
+    ) == egl::FALSE as egl::types::EGLBoolean
+    {
+        Err(format!("unable to get egl configs: {:#x}", egl::GetError()))?
+    }
+    for &config in configs.iter().take(num_configs as usize) {
+        let mut visual_id: i32 = 0;
+        egl::GetConfigAttrib(
+            display,
+            config,
+            egl::NATIVE_VISUAL_ID as i32,
+            &mut visual_id,
+        );
+        if visual_id as Visualid == visual {
+            return Ok(config);
+        }
+    }
+    Err(format!("no egl config matches visual {}", visual))?
+}
@@ -13,6 +13,19 @@ pub enum CompError {
     IoErr(std::io::Error),
     NulErr(NulError),
     IntConvertError(TryFromIntError),
+    PngErr(png::EncodingError),
+}
+
+impl CompError {
+    /// the server rejected a request, e.g. because the window it was about is already gone,
+    /// rather than the connection failing
+    pub fn is_x11_error(&self) -> bool {
+        match self {
+            CompError::Reply(ReplyError::X11Error(_)) => true,
+            CompError::ReplyOrId(ReplyOrIdError::X11Error(_)) => true,
+            _ => false,
+        }
+    }
 }
 
 // pub struct TestErr<'a, T: Error> {
@@ -1,4 +1,7 @@
-use x11rb::protocol::xproto::Atom;
+use crate::errors;
+
+use x11rb::protocol::xproto::{Atom, AtomEnum, ConnectionExt, Window};
+use x11rb::rust_connection::ReplyError;
 
 macro_rules! back_to_enum {
     ($(#[$meta:meta])* $vis:vis enum $name:ident {
@@ -28,3 +31,110 @@ back_to_enum! {
         NetActiveWindow = 252,
     }
 }
+
+x11rb::atom_manager! {
+    pub Atoms: AtomsCookie {
+        WM_STATE,
+        _NET_WM_WINDOW_TYPE,
+        _NET_WM_WINDOW_TYPE_NORMAL,
+        _NET_WM_WINDOW_TYPE_DOCK,
+        _NET_WM_STATE,
+        _NET_WM_STATE_FULLSCREEN,
+        _NET_WM_OPAQUE_REGION,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WinType {
+    Normal,
+    Dock,
+    /// any type we don't care about (yet)
+    Other,
+}
+
+impl WinType {
+    /// windows without _NET_WM_WINDOW_TYPE are normal, as per the EWMH spec
+    pub fn from_atoms(atoms: &Atoms, types: &[Atom]) -> WinType {
+        // the list is in order of preference, so the first one we know wins
+        for &t in types {
+            if t == atoms._NET_WM_WINDOW_TYPE_NORMAL {
+                return WinType::Normal;
+            }
+            if t == atoms._NET_WM_WINDOW_TYPE_DOCK {
+                return WinType::Dock;
+            }
+        }
+        match types.len() {
+            0 => WinType::Normal,
+            _ => WinType::Other,
+        }
+    }
+}
+
+pub fn get_atom_list(
+    conn: &impl x11rb::connection::Connection,
+    window: Window,
+    property: Atom,
+) -> Result<Vec<Atom>, errors::CompError> {
+    let reply = conn
+        .get_property(false, window, property, AtomEnum::ATOM, 0, u32::MAX)?
+        .reply()?;
+    let ret = match reply.value32() {
+        Some(atoms) => atoms.collect(),
+        None => vec![],
+    };
+    Ok(ret)
+}
+
+pub fn get_cardinal_list(
+    conn: &impl x11rb::connection::Connection,
+    window: Window,
+    property: Atom,
+) -> Result<Vec<u32>, errors::CompError> {
+    let reply = conn
+        .get_property(false, window, property, AtomEnum::CARDINAL, 0, u32::MAX)?
+        .reply()?;
+    let ret = match reply.value32() {
+        Some(values) => values.collect(),
+        None => vec![],
+    };
+    Ok(ret)
+}
+
+pub fn has_property(
+    conn: &impl x11rb::connection::Connection,
+    window: Window,
+    property: Atom,
+) -> Result<bool, errors::CompError> {
+    let reply = conn
+        .get_property(false, window, property, AtomEnum::ANY, 0, 0)?
+        .reply()?;
+    Ok(reply.type_ != x11rb::NONE)
+}
+
+/// finds the client window (the one with WM_STATE) of a top level window
+/// reparenting window managers put the client inside a frame, so we have to search the children
+/// windows can be destroyed while we look, those don't have a client
+pub fn find_client(
+    conn: &impl x11rb::connection::Connection,
+    atoms: &Atoms,
+    window: Window,
+) -> Result<Option<Window>, errors::CompError> {
+    match has_property(conn, window, atoms.WM_STATE) {
+        Ok(true) => return Ok(Some(window)),
+        Ok(false) => (),
+        Err(e) if e.is_x11_error() => return Ok(None),
+        Err(e) => return Err(e),
+    }
+    let children = match conn.query_tree(window)?.reply() {
+        Ok(tree) => tree.children,
+        Err(ReplyError::X11Error(_)) => return Ok(None),
+        Err(e) => Err(e)?,
+    };
+    for child in children {
+        if let Some(client) = find_client(conn, atoms, child)? {
+            return Ok(Some(client));
+        }
+    }
+    Ok(None)
+}
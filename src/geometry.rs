@@ -0,0 +1,81 @@
+use crate::errors;
+
+/// number of floats per vertex: x,y, u,v
+pub const VERT_SIZE: usize = 4;
+
+/// vertex/index data for drawing a window (or the screen)
+/// positions go from (0,0) in the top left to (1,-1) in the bottom right,
+/// the vertex shader scales that to the window rect
+#[derive(Debug, Clone)]
+pub struct Mesh {
+    pub verts: Vec<f32>,
+    pub indices: Vec<u32>,
+}
+
+impl Mesh {
+    /// the mesh used for windows unless a shader asks for something else
+    pub fn quad() -> Mesh {
+        Mesh::grid(1, 1)
+    }
+
+    /// a quad split into `cols` x `rows` cells, for shaders that deform the window (e.g. wobbly windows)
+    pub fn grid(cols: u32, rows: u32) -> Mesh {
+        let mut ret = Mesh {
+            verts: Vec::with_capacity(((cols + 1) * (rows + 1)) as usize * VERT_SIZE),
+            indices: Vec::with_capacity((cols * rows * 6) as usize),
+        };
+        for row in 0..=rows {
+            for col in 0..=cols {
+                let u = col as f32 / cols as f32;
+                let v = row as f32 / rows as f32;
+                // y goes down the screen, v goes up the texture
+                ret.verts.extend_from_slice(&[u, -v, u, 1.0 - v]);
+            }
+        }
+        for row in 0..rows {
+            for col in 0..cols {
+                let top_left = row * (cols + 1) + col;
+                let bottom_left = top_left + cols + 1;
+                ret.indices.extend_from_slice(&[
+                    top_left,
+                    bottom_left,
+                    top_left + 1,
+                    top_left + 1,
+                    bottom_left,
+                    bottom_left + 1,
+                ]);
+            }
+        }
+        ret
+    }
+
+    pub fn num_verts(&self) -> usize {
+        self.verts.len() / VERT_SIZE
+    }
+
+    pub fn validate(&self) -> Result<(), errors::CompError> {
+        match (self.verts.len(), self.indices.len()) {
+            (v, _) if v % VERT_SIZE != 0 => Err(format!(
+                "vertices not a multiple of 4 (must be x,y,u,v where u,v are texture coords): {}",
+                v
+            ))?,
+            (_, i) if i % 3 != 0 => Err(format!(
+                "indices not a multiple of 3 (must be triangles): {}",
+                i
+            ))?,
+            (v, i) if v < 3 * VERT_SIZE || i < 3 => Err(format!(
+                "must specify at least one triangle: verts:{} indices{}",
+                v, i
+            ))?,
+            _ => (),
+        }
+        if let Some(i) = self
+            .indices
+            .iter()
+            .find(|&&i| i as usize >= self.num_verts())
+        {
+            Err(format!("indices contain out of range vertex: {}", i))?
+        }
+        Ok(())
+    }
+}
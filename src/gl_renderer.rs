@@ -1,16 +1,30 @@
+use crate::blur;
+use crate::damage;
+#[cfg(feature = "egl")]
+use crate::egl;
+#[cfg(feature = "egl")]
+use crate::egl_context;
 use crate::errors;
+use crate::geometry;
 use crate::gl;
 use crate::glx;
+use crate::hud;
+use crate::renderer;
 use crate::win;
 use crate::xlib;
 
+use std::cell::Cell;
+use std::collections::{HashMap, VecDeque};
 use std::convert::TryInto;
 use std::ffi::{c_void, CString};
 use std::fmt::Debug;
-use std::mem::size_of;
+use std::mem::{size_of, size_of_val};
 use std::ptr::{null, null_mut};
 
-use x11rb::protocol::xproto::Window;
+use x11rb::protocol::shm;
+use x11rb::protocol::shm::ConnectionExt as shm_ConnectionExt;
+use x11rb::protocol::xproto::{ConnectionExt as xproto_ConnectionExt, ImageFormat, Window};
+use x11rb::rust_connection::ReplyError;
 
 const PIXMAP_ATTRS: [i32; 5] = [
     glx::TEXTURE_TARGET_EXT as i32,
@@ -20,39 +34,313 @@ const PIXMAP_ATTRS: [i32; 5] = [
     xlib::None as i32,
 ];
 
-const WIN_RECT_UNIFORM_NAME: &'static str = "win_rect";
+pub const DEFAULT_WIN_VS_PATH: &'static str = "./shaders/default_vs.glsl";
+pub const DEFAULT_WIN_FS_PATH: &'static str = "./shaders/default_fs.glsl";
+pub const SCREEN_VS_PATH: &'static str = "./shaders/screen_vs.glsl";
+pub const SCREEN_FS_PATH: &'static str = "./shaders/screen_fs.glsl";
+/// for debug drawing, with the screen vertex shader
+pub const SOLID_FS_PATH: &'static str = "./shaders/solid_fs.glsl";
+
 const SCREEN_RECT_UNIFORM_NAME: &'static str = "screen_rect";
 const WIN_TEXTURE_UNIFORM_NAME: &'static str = "win_texture";
 const BG_TEXTURE_UNIFORM_NAME: &'static str = "bg_texture";
+const Y_INVERTED_UNIFORM_NAME: &'static str = "y_inverted";
+const SOLID_COLOR_UNIFORM_NAME: &'static str = "color";
 const SCREEN_TEXTURE_UNIFORM_NAME: &'static str = "screen_texture";
 
 #[derive(Debug)]
-struct FboTexture {
-    fbo: gl::types::GLuint,
-    texture: gl::types::GLuint,
+pub(crate) struct FboTexture {
+    pub fbo: gl::types::GLuint,
+    pub texture: gl::types::GLuint,
+}
+
+impl FboTexture {
+    /// reallocate the texture storage, the contents are undefined afterwards
+    pub unsafe fn resize(&self, width: u16, height: u16) -> Result<(), errors::CompError> {
+        gl::BindTexture(gl::TEXTURE_2D, self.texture);
+        gl::TexImage2D(
+            gl::TEXTURE_2D,
+            0,
+            gl::RGB.try_into()?,
+            width as i32,
+            height as i32,
+            0,
+            gl::RGB,
+            gl::UNSIGNED_BYTE,
+            null(),
+        );
+        Ok(())
+    }
+
+    pub unsafe fn delete(&self) {
+        gl::DeleteFramebuffers(1, &self.fbo);
+        gl::DeleteTextures(1, &self.texture);
+    }
+}
+
+/// per window data for the instance buffer, must match the instanced attributes of the window vertex shader
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct WinInstance {
+    /// x, y, width, height of the whole window
+    pub rect: [f32; 4],
+    /// the part of the window that's drawn, from 0 to 1 with y going up like texture coords
+    pub uv: [f32; 4],
+    /// corner radius, dim, opacity, 1 if the background texture is bound for the window
+    pub params: [f32; 4],
+    /// which of the batch's textures is the window's, 1 if the texture is y inverted
+    pub texture: [f32; 2],
+}
+
+impl WinInstance {
+    /// draws `part` of the window at `rect`, both in root coordinates
+    fn new(rect: &win::Rect, part: &win::Rect, params: [f32; 4], texture: [f32; 2]) -> WinInstance {
+        let (width, height) = (rect.width as f32, rect.height as f32);
+        WinInstance {
+            rect: [rect.x as f32, rect.y as f32, width, height],
+            uv: [
+                (part.x as i32 - rect.x as i32) as f32 / width,
+                // from the bottom
+                (rect.y as i32 + rect.height as i32 - part.y as i32 - part.height as i32) as f32
+                    / height,
+                part.width as f32 / width,
+                part.height as f32 / height,
+            ],
+            params,
+            texture,
+        }
+    }
 }
 
+const WIN_RECT_ATTRIB: gl::types::GLuint = 2;
+const WIN_UV_ATTRIB: gl::types::GLuint = 3;
+const WIN_PARAMS_ATTRIB: gl::types::GLuint = 4;
+const WIN_TEXTURE_ATTRIB: gl::types::GLuint = 5;
+
+/// windows with the default shader are drawn together, each with its own texture unit
+/// must match the size of win_texture in the default fragment shader
+const BATCH_TEXTURES: usize = 8;
+/// texture units, window textures take BATCH_TEXTURES units from WIN_TEXTURE_UNIT
+const BG_TEXTURE_UNIT: u32 = 0;
+const WIN_TEXTURE_UNIT: u32 = 1;
+
+/// a mesh uploaded to the gpu, windows also have an instance buffer holding per window data
 #[derive(Debug)]
-pub struct WindowDrawDesc {
+pub(crate) struct GpuMesh {
     vao: gl::types::GLuint,
-    win_shader: gl::types::GLuint,
-    screen_shader: gl::types::GLuint,
+    /// 0 if the mesh isn't drawn instanced
+    instance_vbo: gl::types::GLuint,
+    index_count: gl::types::GLsizei,
+}
 
-    target: FboTexture,
-    background: FboTexture,
+impl GpuMesh {
+    pub unsafe fn new(mesh: &geometry::Mesh) -> Result<GpuMesh, errors::CompError> {
+        mesh.validate()?;
+        let mut ret = GpuMesh {
+            vao: 0,
+            instance_vbo: 0,
+            index_count: mesh.indices.len().try_into()?,
+        };
+        let mut vbo: gl::types::GLuint = 0;
+        let mut ebo: gl::types::GLuint = 0;
 
-    win_rect_uniform_handle: gl::types::GLint,
+        gl::GenVertexArrays(1, &mut ret.vao as *mut gl::types::GLuint);
+        gl::GenBuffers(1, &mut vbo as *mut gl::types::GLuint);
+        gl::GenBuffers(1, &mut ebo as *mut gl::types::GLuint);
+
+        gl::BindVertexArray(ret.vao);
+        gl::BindBuffer(gl::ARRAY_BUFFER, vbo);
+        gl::BufferData(
+            gl::ARRAY_BUFFER,
+            (mesh.verts.len() * size_of::<f32>()) as gl::types::GLsizeiptr,
+            mesh.verts.as_ptr() as *const c_void,
+            gl::STATIC_DRAW,
+        );
+        // position
+        gl::VertexAttribPointer(
+            0,
+            2,
+            gl::FLOAT,
+            gl::FALSE,
+            (geometry::VERT_SIZE * size_of::<f32>()) as gl::types::GLsizei,
+            0 as *const c_void,
+        );
+        gl::EnableVertexAttribArray(0);
+        // texture coords
+        gl::VertexAttribPointer(
+            1,
+            2,
+            gl::FLOAT,
+            gl::FALSE,
+            (geometry::VERT_SIZE * size_of::<f32>()) as gl::types::GLsizei,
+            (2 * size_of::<f32>()) as *const c_void,
+        );
+        gl::EnableVertexAttribArray(1);
+
+        gl::BindBuffer(gl::ELEMENT_ARRAY_BUFFER, ebo);
+        gl::BufferData(
+            gl::ELEMENT_ARRAY_BUFFER,
+            (mesh.indices.len() * size_of::<u32>()) as isize,
+            mesh.indices.as_ptr() as *const c_void,
+            gl::STATIC_DRAW,
+        );
+
+        Ok(ret)
+    }
+
+    /// a mesh drawn with `draw_instances`, with `WinInstance`s as the per instance attributes
+    pub unsafe fn new_instanced(mesh: &geometry::Mesh) -> Result<GpuMesh, errors::CompError> {
+        let mut ret = GpuMesh::new(mesh)?;
+        gl::GenBuffers(1, &mut ret.instance_vbo as *mut gl::types::GLuint);
+        gl::BindVertexArray(ret.vao);
+        // per window attributes, advance once per instance instead of per vertex
+        gl::BindBuffer(gl::ARRAY_BUFFER, ret.instance_vbo);
+        for attrib in [
+            WIN_RECT_ATTRIB,
+            WIN_UV_ATTRIB,
+            WIN_PARAMS_ATTRIB,
+            WIN_TEXTURE_ATTRIB,
+        ] {
+            gl::VertexAttribDivisor(attrib, 1);
+            gl::EnableVertexAttribArray(attrib);
+        }
+        ret.point_instances(0);
+        Ok(ret)
+    }
+
+    /// upload the data for everything that will be drawn this frame, in one go
+    pub unsafe fn upload_instances(&self, instances: &[WinInstance]) {
+        gl::BindBuffer(gl::ARRAY_BUFFER, self.instance_vbo);
+        gl::BufferData(
+            gl::ARRAY_BUFFER,
+            size_of_val(instances) as gl::types::GLsizeiptr,
+            instances.as_ptr() as *const c_void,
+            gl::STREAM_DRAW,
+        );
+    }
+
+    /// point the instanced attributes at the `first`th instance (no base instance in gl 3.3)
+    unsafe fn point_instances(&self, first: usize) {
+        let base = first * size_of::<WinInstance>();
+        let stride = size_of::<WinInstance>() as gl::types::GLsizei;
+        gl::BindBuffer(gl::ARRAY_BUFFER, self.instance_vbo);
+        for (attrib, offset, size) in [
+            (WIN_RECT_ATTRIB, 0, 4),
+            (WIN_UV_ATTRIB, 4, 4),
+            (WIN_PARAMS_ATTRIB, 8, 4),
+            (WIN_TEXTURE_ATTRIB, 12, 2),
+        ] {
+            gl::VertexAttribPointer(
+                attrib,
+                size,
+                gl::FLOAT,
+                gl::FALSE,
+                stride,
+                (base + offset * size_of::<f32>()) as *const c_void,
+            );
+        }
+    }
+
+    /// draw the mesh once without caring about instances, e.g. a full screen pass
+    pub unsafe fn draw(&self) {
+        gl::BindVertexArray(self.vao);
+        gl::DrawElements(gl::TRIANGLES, self.index_count, gl::UNSIGNED_INT, null());
+    }
+
+    /// draw `count` instances from the instance buffer, starting at `first`
+    pub unsafe fn draw_instances(&self, first: usize, count: usize) {
+        gl::BindVertexArray(self.vao);
+        self.point_instances(first);
+        gl::DrawElementsInstanced(
+            gl::TRIANGLES,
+            self.index_count,
+            gl::UNSIGNED_INT,
+            null(),
+            count as gl::types::GLsizei,
+        );
+    }
+}
+
+/// a program for drawing windows, with the uniforms every window shader has to use
+#[derive(Debug)]
+struct WinShader {
+    program: gl::types::GLuint,
     screen_rect_uniform_handle: gl::types::GLint,
     win_texture_uniform_handle: gl::types::GLint,
+    /// -1 if the shader never samples the background, so it doesn't need a copy of it
     bg_texture_uniform_handle: gl::types::GLint,
+    /// -1 if the shader ignores which way up the window texture is,
+    /// the default shader gets it from the instance data instead
+    y_inverted_uniform_handle: gl::types::GLint,
+}
+
+impl WinShader {
+    unsafe fn new(vs_source: &String, fs_source: &String) -> Result<WinShader, errors::CompError> {
+        let program = create_shader(
+            CString::new(vs_source.as_bytes())?,
+            CString::new(fs_source.as_bytes())?,
+        )?;
+        // test if shader has uniforms we need
+        let get_uniform = |name: &str| -> Result<gl::types::GLint, errors::CompError> {
+            let ret = gl::GetUniformLocation(program, CString::new(name)?.as_ptr());
+            if ret < 0 {
+                gl::DeleteProgram(program);
+                Err(format!(
+                    "the window shader does not define or does not use '{}'",
+                    name
+                ))?
+            }
+            Ok(ret)
+        };
+        Ok(WinShader {
+            screen_rect_uniform_handle: get_uniform(SCREEN_RECT_UNIFORM_NAME)?,
+            win_texture_uniform_handle: get_uniform(WIN_TEXTURE_UNIFORM_NAME)?,
+            bg_texture_uniform_handle: gl::GetUniformLocation(
+                program,
+                CString::new(BG_TEXTURE_UNIFORM_NAME)?.as_ptr(),
+            ),
+            y_inverted_uniform_handle: gl::GetUniformLocation(
+                program,
+                CString::new(Y_INVERTED_UNIFORM_NAME)?.as_ptr(),
+            ),
+            program,
+        })
+    }
+
+    unsafe fn delete(&self) {
+        gl::DeleteProgram(self.program);
+    }
+}
+
+#[derive(Debug)]
+pub struct WindowDrawDesc {
+    /// full screen quad, for copying between framebuffers
+    screen_mesh: GpuMesh,
+    win_mesh: GpuMesh,
+    /// `win_mesh` isn't a quad, windows are drawn whole so effect shaders can deform them
+    custom_mesh: bool,
+    win_shader: WinShader,
+    /// kept so per window fragment shaders can be paired with it
+    win_vs_source: String,
+    /// kept so the default shader can be rebuilt with different defines
+    win_fs_source: String,
+    /// window textures are GL_TEXTURE_RECTANGLE, window shaders are built with TEXTURE_RECTANGLE defined
+    texture_rectangle: bool,
+    /// per window fragment shaders by path, `None` if it failed to compile
+    custom_shaders: HashMap<String, Option<WinShader>>,
+    screen_shader: gl::types::GLuint,
+
+    /// every window is blended straight into this
+    target: FboTexture,
+    /// copies of `target` under windows that need to sample what's behind them, only valid there
+    background: FboTexture,
 
     screen_texture_uniform_handle: gl::types::GLint,
 }
 
 impl WindowDrawDesc {
     pub fn new_shader_paths(
-        verts: &Vec<f32>,
-        indices: &Vec<u32>,
+        win_mesh: Option<&geometry::Mesh>,
 
         win_vs_path: &str,
         win_fs_path: &str,
@@ -63,8 +351,7 @@ impl WindowDrawDesc {
         screen_height: u16,
     ) -> Result<WindowDrawDesc, errors::CompError> {
         WindowDrawDesc::new(
-            verts,
-            indices,
+            win_mesh,
             &std::fs::read_to_string(win_vs_path)?,
             &std::fs::read_to_string(win_fs_path)?,
             &std::fs::read_to_string(screen_vs_path)?,
@@ -73,9 +360,9 @@ impl WindowDrawDesc {
             screen_height,
         )
     }
+    /// `win_mesh` is for effect shaders that need more than a quad, `None` uses a quad
     pub fn new(
-        verts: &Vec<f32>,
-        indices: &Vec<u32>,
+        win_mesh: Option<&geometry::Mesh>,
 
         win_vs_source: &String,
         win_fs_source: &String,
@@ -85,74 +372,25 @@ impl WindowDrawDesc {
         screen_width: u16,
         screen_height: u16,
     ) -> Result<WindowDrawDesc, errors::CompError> {
-        let mut ret = WindowDrawDesc {
-            vao: 0,
-            win_shader: 0,
-            screen_shader: 0,
-
-            target: FboTexture { fbo: 0, texture: 0 },
-            background: FboTexture { fbo: 0, texture: 0 },
-
-            win_rect_uniform_handle: 0,
-            screen_rect_uniform_handle: 0,
-            win_texture_uniform_handle: 0,
-            bg_texture_uniform_handle: 0,
+        let quad = geometry::Mesh::quad();
+        let mut ret = unsafe {
+            WindowDrawDesc {
+                screen_mesh: GpuMesh::new(&quad)?,
+                win_mesh: GpuMesh::new_instanced(win_mesh.unwrap_or(&quad))?,
+                custom_mesh: win_mesh.is_some(),
+                win_shader: WinShader::new(win_vs_source, win_fs_source)?,
+                win_vs_source: win_vs_source.clone(),
+                win_fs_source: win_fs_source.clone(),
+                texture_rectangle: false,
+                custom_shaders: HashMap::new(),
+                screen_shader: 0,
 
-            screen_texture_uniform_handle: 0,
-        };
-
-        let mut vbo: gl::types::GLuint = 0;
-        let mut ebo: gl::types::GLuint = 0;
-
-        unsafe {
-            ret.win_shader = create_shader(
-                CString::new(win_vs_source.as_bytes())?,
-                CString::new(win_fs_source.as_bytes())?,
-            )?;
+                target: FboTexture { fbo: 0, texture: 0 },
+                background: FboTexture { fbo: 0, texture: 0 },
 
-            // test if shader has uniforms we need
-            gl::UseProgram(ret.win_shader);
-            ret.win_rect_uniform_handle = gl::GetUniformLocation(
-                ret.win_shader,
-                CString::new(WIN_RECT_UNIFORM_NAME)?.as_ptr(),
-            );
-            ret.screen_rect_uniform_handle = gl::GetUniformLocation(
-                ret.win_shader,
-                CString::new(SCREEN_RECT_UNIFORM_NAME)?.as_ptr(),
-            );
-            ret.win_texture_uniform_handle = gl::GetUniformLocation(
-                ret.win_shader,
-                CString::new(WIN_TEXTURE_UNIFORM_NAME)?.as_ptr(),
-            );
-            ret.bg_texture_uniform_handle = gl::GetUniformLocation(
-                ret.win_shader,
-                CString::new(BG_TEXTURE_UNIFORM_NAME)?.as_ptr(),
-            );
-            if ret.win_rect_uniform_handle < 0 {
-                Err(format!(
-                    "the window shader does not define or does not use '{}'",
-                    WIN_RECT_UNIFORM_NAME
-                ))?
-            }
-            if ret.screen_rect_uniform_handle < 0 {
-                Err(format!(
-                    "the window shader does not define or does not use '{}'",
-                    SCREEN_RECT_UNIFORM_NAME
-                ))?
-            }
-            if ret.win_texture_uniform_handle < 0 {
-                Err(format!(
-                    "the window shader does not define or does not use '{}'",
-                    WIN_TEXTURE_UNIFORM_NAME
-                ))?
-            }
-            if ret.bg_texture_uniform_handle < 0 {
-                Err(format!(
-                    "the window shader does not define or does not use '{}'",
-                    BG_TEXTURE_UNIFORM_NAME
-                ))?
+                screen_texture_uniform_handle: 0,
             }
-        }
+        };
 
         unsafe {
             ret.screen_shader = create_shader(
@@ -171,78 +409,119 @@ impl WindowDrawDesc {
             }
         }
 
-        if let Some(i) = indices.iter().find(|&&i| i >= verts.len() as u32) {
-            Err(format!("indices contain out of range vertex: {}", i))?
+        unsafe {
+            ret.target = gen_framebuffer(screen_width, screen_height)?;
+            ret.background = gen_framebuffer(screen_width, screen_height)?;
         }
-        match (verts.len(), indices.len()) {
-            (v, _) if v % 4 != 0 => Err(format!(
-                "vertices not a multiple of 4 (must be x,y,u,v where u,v are texture coords): {}",
-                v
-            ))?,
-            (_, i) if i % 3 != 0 => Err(format!(
-                "indices not a multiple of 3 (must be triangles): {}",
-                i
-            ))?,
-            (v, i) if v < 3 * 4 || i < 3 => Err(format!(
-                "must specify at least one triangle: verts:{} indices{}",
-                v, i
-            ))?,
-            _ => (),
+
+        Ok(ret)
+    }
+
+    /// swap in a new window shader, the old one is kept if the new one doesn't compile
+    pub fn reload_win_shader(
+        &mut self,
+        win_vs_path: &str,
+        win_fs_path: &str,
+    ) -> Result<(), errors::CompError> {
+        let vs_source = std::fs::read_to_string(win_vs_path)?;
+        let fs_source = std::fs::read_to_string(win_fs_path)?;
+        let shader = unsafe { WinShader::new(&vs_source, &self.with_defines(&fs_source))? };
+        self.replace_win_shader(shader);
+        self.win_vs_source = vs_source;
+        self.win_fs_source = fs_source;
+        Ok(())
+    }
+
+    /// window textures are GL_TEXTURE_RECTANGLE, which shaders sample in pixels rather than from 0 to 1
+    pub fn set_texture_rectangle(&mut self, enabled: bool) -> Result<(), errors::CompError> {
+        if self.texture_rectangle == enabled {
+            return Ok(());
         }
+        self.texture_rectangle = enabled;
+        let shader = unsafe {
+            WinShader::new(&self.win_vs_source, &self.with_defines(&self.win_fs_source))?
+        };
+        self.replace_win_shader(shader);
+        Ok(())
+    }
 
+    fn replace_win_shader(&mut self, shader: WinShader) {
         unsafe {
-            gl::GenVertexArrays(1, &mut ret.vao as *mut gl::types::GLuint);
-            gl::GenBuffers(1, &mut vbo as *mut gl::types::GLuint);
-            gl::GenBuffers(1, &mut ebo as *mut gl::types::GLuint);
-
-            gl::BindVertexArray(ret.vao);
-            gl::BindBuffer(gl::ARRAY_BUFFER, vbo);
-            gl::BufferData(
-                gl::ARRAY_BUFFER,
-                (verts.len() * size_of::<f32>()) as gl::types::GLsizeiptr,
-                verts.as_ptr() as *const c_void,
-                gl::STATIC_DRAW,
-            );
-            // position
-            gl::VertexAttribPointer(
-                0,
-                2,
-                gl::FLOAT,
-                gl::FALSE,
-                (4 * size_of::<f32>()) as gl::types::GLsizei,
-                0 as *const c_void,
-            );
-            gl::EnableVertexAttribArray(0);
-            // texture coords
-            gl::VertexAttribPointer(
-                1,
-                2,
-                gl::FLOAT,
-                gl::FALSE,
-                (4 * size_of::<f32>()) as gl::types::GLsizei,
-                (2 * size_of::<f32>()) as *const c_void,
-            );
-            gl::EnableVertexAttribArray(1);
-
-            gl::BindBuffer(gl::ELEMENT_ARRAY_BUFFER, ebo);
-            gl::BufferData(
-                gl::ELEMENT_ARRAY_BUFFER,
-                (indices.len() * size_of::<u32>()) as isize,
-                indices.as_ptr() as *const c_void,
-                gl::STATIC_DRAW,
-            );
+            self.win_shader.delete();
+            for shader in self.custom_shaders.values().flatten() {
+                shader.delete();
+            }
         }
+        self.win_shader = shader;
+        // recompiled against the new vertex shader and defines the next time they're used
+        self.custom_shaders.clear();
+    }
 
-        unsafe {
-            ret.target = gen_framebuffer(screen_width, screen_height)?;
-            ret.background = gen_framebuffer(screen_width, screen_height)?;
+    /// `fs_source` with the defines for how window textures are bound, right after its #version line
+    fn with_defines(&self, fs_source: &str) -> String {
+        match (self.texture_rectangle, fs_source.split_once('\n')) {
+            (true, Some((version, rest))) => {
+                format!("{}\n#define TEXTURE_RECTANGLE\n{}", version, rest)
+            }
+            _ => fs_source.to_string(),
         }
+    }
 
-        Ok(ret)
+    /// compile a per window fragment shader if it hasn't been tried yet
+    fn load_custom_shader(&mut self, fs_path: &String) {
+        if self.custom_shaders.contains_key(fs_path) {
+            return;
+        }
+        let shader = std::fs::read_to_string(fs_path)
+            .map_err(errors::CompError::from)
+            .and_then(|fs_source| unsafe {
+                WinShader::new(&self.win_vs_source, &self.with_defines(&fs_source))
+            });
+        let shader = match shader {
+            Ok(shader) => Some(shader),
+            Err(e) => {
+                println!(
+                    "warning: unable to load window shader '{}', using the default: {:?}",
+                    fs_path, e
+                );
+                None
+            }
+        };
+        self.custom_shaders.insert(fs_path.clone(), shader);
+    }
+
+    /// the default shader if `fs_path` is `None` or didn't compile
+    fn shader_for(&self, fs_path: Option<&String>) -> &WinShader {
+        fs_path
+            .and_then(|p| self.custom_shaders.get(p))
+            .and_then(|s| s.as_ref())
+            .unwrap_or(&self.win_shader)
+    }
+
+    /// whether a window drawn with `fs_path` needs a copy of what's behind it
+    /// the default shader only looks at it to put the window over its blurred background,
+    /// custom shaders get it whenever they sample it
+    fn needs_background(&self, fs_path: Option<&String>, blur: bool) -> bool {
+        let shader = self.shader_for(fs_path);
+        blur || (!std::ptr::eq(shader, &self.win_shader) && shader.bg_texture_uniform_handle >= 0)
+    }
+
+    /// whether a window drawn with `fs_path` can share an instanced draw with others,
+    /// windows with effect shaders or a custom mesh are drawn one at a time
+    fn batched(&self, fs_path: Option<&String>) -> bool {
+        !self.custom_mesh && std::ptr::eq(self.shader_for(fs_path), &self.win_shader)
+    }
+
+    pub fn resize(&self, screen_width: u16, screen_height: u16) -> Result<(), errors::CompError> {
+        unsafe {
+            self.target.resize(screen_width, screen_height)?;
+            self.background.resize(screen_width, screen_height)?;
+        }
+        Ok(())
     }
 }
 
-unsafe fn create_shader(
+pub(crate) unsafe fn create_shader(
     vs_source: CString,
     fs_source: CString,
 ) -> Result<gl::types::GLuint, errors::CompError> {
@@ -300,7 +579,7 @@ unsafe fn compile_shader(
     Ok(shader)
 }
 
-unsafe fn gen_framebuffer(
+pub(crate) unsafe fn gen_framebuffer(
     screen_width: u16,
     screen_height: u16,
 ) -> Result<FboTexture, errors::CompError> {
@@ -333,6 +612,17 @@ unsafe fn gen_framebuffer(
         // depends on what kind of shaders you decide to use i suppose?
         gl::LINEAR as gl::types::GLint,
     );
+    // so sampling around the edges (e.g. blurring) doesn't wrap to the other side of the screen
+    gl::TexParameteri(
+        gl::TEXTURE_2D,
+        gl::TEXTURE_WRAP_S,
+        gl::CLAMP_TO_EDGE as gl::types::GLint,
+    );
+    gl::TexParameteri(
+        gl::TEXTURE_2D,
+        gl::TEXTURE_WRAP_T,
+        gl::CLAMP_TO_EDGE as gl::types::GLint,
+    );
     gl::FramebufferTexture2D(
         gl::FRAMEBUFFER,
         gl::COLOR_ATTACHMENT0,
@@ -346,140 +636,1252 @@ unsafe fn gen_framebuffer(
     })
 }
 
+/// how many frames can be in flight on the gpu before we wait for the oldest one
+const READBACK_BUFFERS: usize = 3;
+
+/// a frame being copied into a pixel buffer, which the gpu does in the background
+#[derive(Debug)]
+struct PendingReadback {
+    pbo: gl::types::GLuint,
+    fence: gl::types::GLsync,
+    width: u16,
+    height: u16,
+}
+
+/// frames of timer queries that can be waiting on the gpu, older ones are read even if that stalls
+const TIMER_FRAMES: usize = 4;
+
+/// gpu time of each pass, from pairs of timestamp queries around every stretch of work
+/// passes are interleaved (blur, window, blur, window...), so each one can have many stretches
+#[derive(Debug, Default)]
+struct GpuTimer {
+    free: Vec<gl::types::GLuint>,
+    /// the pass being timed and the query at its start
+    open: Option<(&'static str, gl::types::GLuint)>,
+    /// (pass, start, end) of this frame
+    current: Vec<(&'static str, gl::types::GLuint, gl::types::GLuint)>,
+    /// oldest first
+    in_flight: VecDeque<Vec<(&'static str, gl::types::GLuint, gl::types::GLuint)>>,
+}
+
+impl GpuTimer {
+    unsafe fn query(&mut self) -> gl::types::GLuint {
+        let query = self.free.pop().unwrap_or_else(|| {
+            let mut q = 0;
+            gl::GenQueries(1, &mut q);
+            q
+        });
+        gl::QueryCounter(query, gl::TIMESTAMP);
+        query
+    }
+
+    /// ends whatever pass was being timed
+    unsafe fn begin(&mut self, pass: &'static str) {
+        self.end();
+        self.open = Some((pass, self.query()));
+    }
+
+    unsafe fn end(&mut self) {
+        if let Some((pass, start)) = self.open.take() {
+            let end = self.query();
+            self.current.push((pass, start, end));
+        }
+    }
+
+    unsafe fn end_frame(&mut self) {
+        self.end();
+        self.in_flight.push_back(std::mem::take(&mut self.current));
+    }
+
+    /// times of the newest frame the gpu has finished, older finished frames are dropped
+    unsafe fn collect(&mut self) -> Option<Vec<(&'static str, f32)>> {
+        let mut ret = None;
+        while let Some(frame) = self.in_flight.front() {
+            let mut available = gl::TRUE as gl::types::GLint;
+            if let Some(&(_, _, last)) = frame.last() {
+                gl::GetQueryObjectiv(last, gl::QUERY_RESULT_AVAILABLE, &mut available);
+            }
+            if available == gl::FALSE as gl::types::GLint && self.in_flight.len() <= TIMER_FRAMES {
+                break;
+            }
+            let frame = self.in_flight.pop_front().unwrap_or_default();
+            let mut passes: Vec<(&'static str, f32)> = vec![];
+            for (pass, start, end) in frame {
+                let (mut start_ns, mut end_ns) = (0, 0);
+                gl::GetQueryObjectui64v(start, gl::QUERY_RESULT, &mut start_ns);
+                gl::GetQueryObjectui64v(end, gl::QUERY_RESULT, &mut end_ns);
+                let ms = end_ns.saturating_sub(start_ns) as f32 / 1_000_000.0;
+                match passes.iter_mut().find(|(p, _)| *p == pass) {
+                    Some((_, t)) => *t += ms,
+                    None => passes.push((pass, ms)),
+                }
+                self.free.push(start);
+                self.free.push(end);
+            }
+            ret = Some(passes);
+        }
+        ret
+    }
+}
+
+/// how many frames of damage are kept for buffer age, older back buffers are repainted in full
+const DAMAGE_HISTORY_LEN: usize = 4;
+
+/// where the gl context came from, which decides how window pixmaps become textures and how frames are shown
+#[derive(Debug)]
+pub enum GlPlatform {
+    Glx {
+        display: *mut glx::types::Display,
+        /// fb config the glx pixmaps are created with
+        fb_config: *const c_void,
+        overlay: Window,
+        /// GLX_EXT_buffer_age is supported, so only what changed since the back buffer was shown is presented
+        buffer_age: bool,
+        /// GLX_Y_INVERTED_EXT of `fb_config`, the first row of a bound pixmap is the top of the window
+        y_inverted: bool,
+        /// GLX_EXT_swap_control is supported, so swaps are set to wait for vblank
+        swap_control: bool,
+        /// pixmaps can be bound as textures, otherwise their contents are copied in on the cpu
+        texture_from_pixmap: bool,
+        /// `fb_config` can't bind to GL_TEXTURE_2D, so pixmaps are bound as GL_TEXTURE_RECTANGLE
+        texture_rectangle: bool,
+    },
+    #[cfg(feature = "egl")]
+    Egl(egl_context::EglContext),
+}
+
+/// what the pixmap of a window is bound through
+#[derive(Debug)]
+enum PixmapSource {
+    /// has to be bound with BindTexImageEXT each time it's drawn
+    Glx(glx::types::GLXPixmap),
+    /// stays attached to the texture
+    #[cfg(feature = "egl")]
+    Egl(egl::types::EGLImage),
+    /// copied into the texture through shared memory (or GetImage) wherever it's damaged
+    Upload(x11rb::protocol::xproto::Pixmap),
+}
+
+/// a window, its texture, its pixmap and what to copy from that pixmap into the texture
+type Fetch = (
+    Window,
+    gl::types::GLuint,
+    x11rb::protocol::xproto::Pixmap,
+    win::Rect,
+);
+
+/// memory shared with the X server, which window contents are copied into when they can't be bound
+/// detached from the server with `detach`, otherwise that happens when the connection closes
+#[derive(Debug)]
+struct ShmSegment {
+    id: i32,
+    seg: shm::Seg,
+    addr: *mut c_void,
+    size: usize,
+}
+
+impl ShmSegment {
+    unsafe fn new(
+        conn: &impl x11rb::connection::Connection,
+        size: usize,
+    ) -> Result<ShmSegment, errors::CompError> {
+        let id = libc::shmget(libc::IPC_PRIVATE, size, libc::IPC_CREAT | 0o600);
+        if id < 0 {
+            Err(format!(
+                "unable to create a {} byte shared memory segment",
+                size
+            ))?
+        }
+        let addr = libc::shmat(id, null(), 0);
+        // gone once both we and the server have detached, even if we crash
+        libc::shmctl(id, libc::IPC_RMID, null_mut());
+        if addr as isize == -1 {
+            Err("unable to attach the shared memory segment".to_string())?
+        }
+        let seg = conn.generate_id()?;
+        if conn.shm_attach(seg, id as u32, false)?.check().is_err() {
+            libc::shmdt(addr);
+            Err("the X server was unable to attach the shared memory segment".to_string())?
+        }
+        Ok(ShmSegment {
+            id,
+            seg,
+            addr,
+            size,
+        })
+    }
+
+    fn detach(self, conn: &impl x11rb::connection::Connection) {
+        conn.shm_detach(self.seg).ok();
+    }
+}
+
+impl Drop for ShmSegment {
+    fn drop(&mut self) {
+        unsafe {
+            libc::shmdt(self.addr);
+            // already marked when it was made, this is in case it never got that far
+            libc::shmctl(self.id, libc::IPC_RMID, null_mut());
+        }
+    }
+}
+
+/// what we hold on to for each window so its pixmap can be drawn
+#[derive(Debug)]
+struct GlWin {
+    source: PixmapSource,
+    /// the gl texture of the window backing pixmap
+    texture: gl::types::GLuint,
+    /// the glx pixmap is bound to `texture`, it's left bound between frames
+    /// when the pixmap is uploaded instead, all of it has been at least once
+    bound: Cell<bool>,
+    /// the window was drawn to since it was bound, so it has to be bound again to be sure
+    /// the texture has the new contents (some drivers copy the pixmap when it's bound)
+    stale: Cell<bool>,
+}
+
+/// windows drawn with one instanced draw, or a window drawn on its own with its shader
+struct Batch<'a> {
+    /// the textures sampled, in order of the instances' texture index
+    wins: Vec<&'a GlWin>,
+    /// the first window's index in the frame's visible windows, only it can have a background
+    start: usize,
+    /// where its instances are in the instance buffer
+    first: usize,
+    count: usize,
+    /// one window drawn whole, clipped to what's visible
+    alone: bool,
+}
+
 #[derive(Debug)]
 pub struct GLRenderer {
+    platform: GlPlatform,
+
     // TODO: allow different descs for different windows
     desc: WindowDrawDesc,
+    blur: blur::KawaseBlur,
+
+    wins: HashMap<Window, GlWin>,
+
+    /// oldest first
+    readbacks: VecDeque<PendingReadback>,
+    free_pbos: Vec<gl::types::GLuint>,
+
+    width: u16,
+    height: u16,
+    /// leave finished frames in the target fbo instead of drawing them to the overlay
+    headless: bool,
+
+    timer: GpuTimer,
+    /// damage of the frames presented so far, newest first
+    damage_history: VecDeque<damage::DamageRegion>,
+    /// for uploading window contents without texture_from_pixmap, grown to fit a frame's damage
+    shm: Option<ShmSegment>,
+    /// the server has MIT-SHM, otherwise window contents are read with GetImage
+    has_shm: bool,
+    /// the hud and debug labels are drawn on the cpu and uploaded here right before they're drawn
+    image_texture: gl::types::GLuint,
+    /// fills the viewport with one color, for debug drawing
+    solid_shader: gl::types::GLuint,
+    solid_color_uniform_handle: gl::types::GLint,
 }
 
 // TODO: draw borders
 // TODO: find out what i meant by "draw borders"
 impl GLRenderer {
-    pub fn new(desc: WindowDrawDesc) -> Result<GLRenderer, errors::CompError> {
-        Ok(GLRenderer { desc: desc })
+    /// the platform's context must already be current
+    pub fn new(
+        conn: &impl x11rb::connection::Connection,
+        platform: GlPlatform,
+        mut desc: WindowDrawDesc,
+        blur: blur::KawaseBlur,
+        screen_width: u16,
+        screen_height: u16,
+        headless: bool,
+    ) -> Result<GLRenderer, errors::CompError> {
+        if let GlPlatform::Glx {
+            texture_rectangle: true,
+            ..
+        } = platform
+        {
+            desc.set_texture_rectangle(true)?;
+        }
+        let (solid_shader, solid_color_uniform_handle) = unsafe {
+            let program = create_shader(
+                CString::new(std::fs::read_to_string(SCREEN_VS_PATH)?)?,
+                CString::new(std::fs::read_to_string(SOLID_FS_PATH)?)?,
+            )?;
+            let color =
+                gl::GetUniformLocation(program, CString::new(SOLID_COLOR_UNIFORM_NAME)?.as_ptr());
+            if color < 0 {
+                Err(format!(
+                    "the shader does not define or does not use '{}'",
+                    SOLID_COLOR_UNIFORM_NAME
+                ))?
+            }
+            (program, color)
+        };
+        let mut image_texture = 0;
+        unsafe {
+            gl::GenTextures(1, &mut image_texture);
+            gl::BindTexture(gl::TEXTURE_2D, image_texture);
+            // drawn at its own size, pixel for pixel
+            gl::TexParameteri(
+                gl::TEXTURE_2D,
+                gl::TEXTURE_MIN_FILTER,
+                gl::NEAREST as gl::types::GLint,
+            );
+            gl::TexParameteri(
+                gl::TEXTURE_2D,
+                gl::TEXTURE_MAG_FILTER,
+                gl::NEAREST as gl::types::GLint,
+            );
+        }
+        Ok(GLRenderer {
+            platform,
+
+            desc: desc,
+            blur: blur,
+
+            wins: HashMap::new(),
+
+            readbacks: VecDeque::new(),
+            free_pbos: vec![],
+
+            width: screen_width,
+            height: screen_height,
+            headless,
+
+            timer: GpuTimer::default(),
+            damage_history: VecDeque::with_capacity(DAMAGE_HISTORY_LEN),
+            shm: None,
+            has_shm: conn
+                .extension_information(shm::X11_EXTENSION_NAME)?
+                .is_some(),
+            image_texture,
+            solid_shader,
+            solid_color_uniform_handle,
+        })
     }
 
-    pub unsafe fn reacquire_glx_pixmap(
-        &self,
-        win: &mut win::Win,
-        display: *mut glx::types::Display,
-        config: *const c_void,
-    ) {
-        if win.glx_pixmap != 0 {
-            glx::DestroyGLXPixmap(display, win.glx_pixmap);
-        }
-        if win.texture != 0 {
-            gl::DeleteTextures(1, &win.texture);
-            win.texture = 0;
-        }
-        win.glx_pixmap = glx::CreatePixmap(
-            display,
-            config,
-            win.pixmap as u64,
-            &PIXMAP_ATTRS as *const i32,
-        );
-        gl::GenTextures(1, &mut win.texture);
-        gl::BindTexture(gl::TEXTURE_2D, win.texture);
+    #[cfg_attr(not(feature = "egl"), allow(unused_variables))]
+    unsafe fn bind_win_pixmap(
+        &mut self,
+        win: &win::Win,
+        conn: &impl x11rb::connection::Connection,
+    ) -> Result<(), errors::CompError> {
+        self.release_win_pixmap(win.handle());
+        let target = self.texture_target();
+        let mut texture = 0;
+        gl::GenTextures(1, &mut texture);
+        gl::BindTexture(target, texture);
         // nearest, as the windows should be a 1:1 match
         gl::TexParameteri(
-            gl::TEXTURE_2D,
+            target,
             gl::TEXTURE_MIN_FILTER,
             gl::NEAREST as gl::types::GLint,
         );
         gl::TexParameteri(
-            gl::TEXTURE_2D,
+            target,
             gl::TEXTURE_MAG_FILTER,
             gl::NEAREST as gl::types::GLint,
         );
+        let mut pixmap_attrs = PIXMAP_ATTRS;
+        if target == gl::TEXTURE_RECTANGLE {
+            pixmap_attrs[1] = glx::TEXTURE_RECTANGLE_EXT as i32;
+        }
+        // the alpha byte of a 24 bit pixmap is undefined, but windows are blended by their alpha
+        if !win.argb {
+            pixmap_attrs[3] = glx::TEXTURE_FORMAT_RGB_EXT as i32;
+            gl::TexParameteri(target, gl::TEXTURE_SWIZZLE_A, gl::ONE as gl::types::GLint);
+        }
+        let source = match &self.platform {
+            GlPlatform::Glx {
+                texture_from_pixmap: false,
+                ..
+            } => PixmapSource::Upload(win.pixmap),
+            GlPlatform::Glx {
+                display, fb_config, ..
+            } => PixmapSource::Glx(glx::CreatePixmap(
+                *display,
+                *fb_config,
+                win.pixmap as u64,
+                &pixmap_attrs as *const i32,
+            )),
+            #[cfg(feature = "egl")]
+            GlPlatform::Egl(ctx) => match ctx.create_image(win, conn) {
+                Ok(image) => {
+                    ctx.bind_image(texture, image);
+                    PixmapSource::Egl(image)
+                }
+                Err(e) => {
+                    gl::DeleteTextures(1, &texture);
+                    return Err(e);
+                }
+            },
+        };
+        self.wins.insert(
+            win.handle(),
+            GlWin {
+                source,
+                texture,
+                bound: Cell::new(false),
+                stale: Cell::new(false),
+            },
+        );
+        Ok(())
     }
-    pub fn release_glx_pixmap(&self, win: &mut win::Win, display: *mut glx::types::Display) {
+    unsafe fn release_win_pixmap(&mut self, handle: Window) {
+        if let Some(w) = self.wins.remove(&handle) {
+            match (&self.platform, w.source) {
+                (GlPlatform::Glx { display, .. }, PixmapSource::Glx(pixmap)) => {
+                    if w.bound.get() {
+                        glx::ReleaseTexImageEXT(*display, pixmap, glx::FRONT_EXT as i32);
+                    }
+                    glx::DestroyGLXPixmap(*display, pixmap)
+                }
+                #[cfg(feature = "egl")]
+                (GlPlatform::Egl(ctx), PixmapSource::Egl(image)) => ctx.destroy_image(image),
+                _ => (),
+            }
+            gl::DeleteTextures(1, &w.texture);
+        }
+    }
+
+    /// resize everything that is the size of the screen
+    fn resize_screen(&mut self, width: u16, height: u16) -> Result<(), errors::CompError> {
+        self.width = width;
+        self.height = height;
+        self.desc.resize(width, height)?;
+        self.blur.resize(width, height)?;
         unsafe {
-            glx::DestroyGLXPixmap(display, win.glx_pixmap);
-            gl::DeleteTextures(1, &win.texture);
-            win.texture = 0;
+            gl::Viewport(0, 0, width as i32, height as i32);
         }
+        // the back buffers are new too
+        self.damage_history.clear();
+        Ok(())
     }
 
-    pub fn render(
-        &self,
-        width: u16,
-        height: u16,
-        wins: &win::WinTracker,
-        display: *mut glx::types::Display,
-        overlay: Window,
-        _conn: &impl x11rb::connection::Connection,
-    ) -> Result<(), errors::CompError> {
+    fn render_frame(&mut self, wins: &win::WinTracker) -> Result<(), errors::CompError> {
+        let screen = wins.get_composite_win().rect;
+        // windows that are covered up now still need rebinding when they're drawn again
+        for w in wins.mapped_wins().filter(|w| w.contents_changed()) {
+            if let Some(gw) = self.wins.get(&w.handle()) {
+                gw.stale.set(true);
+            }
+        }
+        for path in wins.mapped_wins().filter_map(|w| w.shader.as_ref()) {
+            self.desc.load_custom_shader(path);
+        }
+        // the target keeps the last frame, so only what changed since is composited again,
+        // the back buffer catches up on what it missed when presenting
+        // the hud and debug drawing go on top of the windows in the target, so they need all of it redrawn
+        let overlays = wins.config.effects.hud
+            || wins.config.effects.debug_damage
+            || wins.config.effects.debug_boxes;
+        let mut paint = damage::DamageRegion::default();
+        match overlays {
+            true => paint.add(screen),
+            false => paint.union(wins.frame_damage()),
+        }
+        // a blurred window samples around itself, which has to be redrawn before it's copied
+        let margin = self.blur.margin(wins.config.blur.offset);
+        for w in wins.mapped_wins().filter(|w| w.settings.blur) {
+            if let Some(r) = w.screen_rect().grow(margin, &screen) {
+                if paint.intersects(&r) {
+                    paint.add(r);
+                }
+            }
+        }
+        // borrowed on their own so the timer can still be used while `visible` is alive
+        let gl_wins = &self.wins;
+        let desc = &self.desc;
+        // the last element is whether the window gets a copy of what's behind it
+        let visible: Vec<(&win::Win, &GlWin, damage::DamageRegion, bool)> = wins
+            .unoccluded_wins()
+            .into_iter()
+            .filter_map(|(w, mut region)| {
+                region.intersect(&paint);
+                if region.is_empty() {
+                    return None;
+                }
+                let needs_background = desc.needs_background(w.shader.as_ref(), w.settings.blur);
+                gl_wins
+                    .get(&w.handle())
+                    .map(|gw| (w, gw, region, needs_background))
+            })
+            .collect();
+
+        // consecutive windows with the default shader are drawn together, each of their visible
+        // rects is an instance, others are drawn alone as a single instance
+        // a window that needs a background starts a new batch, as it's copied after what's below is drawn
+        let mut instances: Vec<WinInstance> = Vec::new();
+        let mut batches: Vec<Batch> = Vec::new();
+        for (i, (w, gw, region, needs_background)) in visible.iter().enumerate() {
+            let alone = !desc.batched(w.shader.as_ref());
+            let joins = !alone
+                && !needs_background
+                && matches!(batches.last(), Some(b) if !b.alone && b.wins.len() < BATCH_TEXTURES);
+            if !joins {
+                batches.push(Batch {
+                    wins: Vec::new(),
+                    start: i,
+                    first: instances.len(),
+                    count: 0,
+                    alone,
+                });
+            }
+            let batch = batches.last_mut().unwrap();
+            let params = [
+                match w.settings.rounded {
+                    true => wins.config.corner_radius,
+                    false => 0.0,
+                },
+                w.dim,
+                w.opacity,
+                *needs_background as i32 as f32,
+            ];
+            let texture = [batch.wins.len() as f32, self.y_inverted(gw) as i32 as f32];
+            batch.wins.push(gw);
+            // the texture has the border in it too
+            let rect = w.screen_rect();
+            let first = instances.len();
+            match alone {
+                true => instances.push(WinInstance::new(&rect, &rect, params, texture)),
+                false => instances.extend(
+                    region
+                        .rects()
+                        .iter()
+                        .map(|r| WinInstance::new(&rect, r, params, texture)),
+                ),
+            }
+            batch.count += instances.len() - first;
+        }
+
         unsafe {
-            gl::BindVertexArray(self.desc.vao);
-            clear_fbo(self.desc.target.fbo);
-            clear_fbo(self.desc.background.fbo);
-            let (mut target, mut background) = (&self.desc.target, &self.desc.background);
+            self.desc.win_mesh.upload_instances(&instances);
+            gl::Enable(gl::SCISSOR_TEST);
+            for r in paint.rects() {
+                self.scissor(r);
+                clear_fbo(self.desc.target.fbo);
+            }
+            gl::Disable(gl::SCISSOR_TEST);
+            let (target, background) = (&self.desc.target, &self.desc.background);
 
-            for w in wins.mapped_wins() {
-                if !w.track_damage {
-                    continue;
+            for batch in batches.iter() {
+                let (w, gw, region, needs_background) = &visible[batch.start];
+                // only what isn't covered up is drawn, so that's all the background that's needed
+                let bounds = region.bounds().unwrap_or_else(|| w.screen_rect());
+                // when it isn't needed the background is still bound, as the target can't be
+                let bg = match (needs_background, w.settings.blur) {
+                    (false, _) => background,
+                    (true, blur) => {
+                        self.timer.begin("background");
+                        let margin = match blur {
+                            true => self.blur.margin(wins.config.blur.offset),
+                            false => 0,
+                        };
+                        self.copy_region(target, background, &bounds, margin);
+                        match blur {
+                            true => {
+                                self.timer.begin("blur");
+                                self.blur.blur(
+                                    &self.desc.screen_mesh,
+                                    background,
+                                    &bounds,
+                                    wins.config.blur.offset,
+                                );
+                                &self.blur.output
+                            }
+                            false => background,
+                        }
+                    }
+                };
+                self.timer.begin("windows");
+                let shader = self.desc.shader_for(w.shader.as_ref());
+                gl::UseProgram(shader.program);
+                match batch.alone {
+                    // effect shaders have a single window texture
+                    true => {
+                        gl::Uniform1i(shader.win_texture_uniform_handle, WIN_TEXTURE_UNIT as i32);
+                        gl::Uniform1i(shader.y_inverted_uniform_handle, self.y_inverted(gw) as i32);
+                    }
+                    false => {
+                        let units: Vec<i32> = (0..BATCH_TEXTURES as i32)
+                            .map(|i| WIN_TEXTURE_UNIT as i32 + i)
+                            .collect();
+                        gl::Uniform1iv(
+                            shader.win_texture_uniform_handle,
+                            BATCH_TEXTURES as i32,
+                            units.as_ptr(),
+                        );
+                    }
                 }
-                (target, background) = (background, target);
+                gl::Uniform1i(shader.bg_texture_uniform_handle, BG_TEXTURE_UNIT as i32);
+                gl::Uniform2f(
+                    shader.screen_rect_uniform_handle,
+                    screen.width as f32,
+                    screen.height as f32,
+                );
+                self.render_batch(batch, region, target, bg);
+            }
+
+            if wins.config.effects.debug_damage || wins.config.effects.debug_boxes {
+                self.timer.begin("debug");
+                self.draw_debug(wins, target.fbo)?;
+            }
+            if wins.config.effects.hud {
+                self.timer.begin("hud");
+                let image = hud::draw(
+                    &wins.stats,
+                    wins.wins()[1..].iter().filter(|w| w.mapped()).count(),
+                    wins.pacer.refresh_rate(),
+                );
+                self.draw_image(image, 0, 0, target.fbo)?;
+            }
+
+            if !self.headless {
+                self.timer.begin("present");
                 gl::UseProgram(self.desc.screen_shader);
                 gl::Uniform1i(self.desc.screen_texture_uniform_handle, 0);
                 gl::ActiveTexture(gl::TEXTURE0);
-                gl::BindTexture(gl::TEXTURE_2D, background.texture);
-                gl::BindFramebuffer(gl::FRAMEBUFFER, target.fbo);
-                gl::DrawElements(gl::TRIANGLES, 6, gl::UNSIGNED_INT, null());
+                gl::BindTexture(gl::TEXTURE_2D, target.texture);
+                gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+                // the hud and the debug drawing change every frame, wherever they are
+                let overlays = wins.config.effects.hud
+                    || wins.config.effects.debug_damage
+                    || wins.config.effects.debug_boxes;
+                match self.repaint_region(wins.frame_damage()) {
+                    Some(region) if !overlays => {
+                        gl::Enable(gl::SCISSOR_TEST);
+                        for r in region.rects() {
+                            self.scissor(r);
+                            self.desc.screen_mesh.draw();
+                        }
+                        gl::Disable(gl::SCISSOR_TEST);
+                    }
+                    _ => self.desc.screen_mesh.draw(),
+                }
 
-                gl::UseProgram(self.desc.win_shader);
-                gl::Uniform1i(self.desc.win_texture_uniform_handle, 0);
-                gl::Uniform1i(self.desc.bg_texture_uniform_handle, 1);
-                gl::Uniform2f(
-                    self.desc.screen_rect_uniform_handle,
-                    width as f32,
-                    height as f32,
-                );
-                self.render_win(w, display, target, background);
+                match &self.platform {
+                    GlPlatform::Glx {
+                        display, overlay, ..
+                    } => glx::SwapBuffers(*display, *overlay as u64),
+                    #[cfg(feature = "egl")]
+                    GlPlatform::Egl(ctx) => ctx.swap_buffers(),
+                }
+                if self.damage_history.len() == DAMAGE_HISTORY_LEN {
+                    self.damage_history.pop_back();
+                }
+                self.damage_history.push_front(wins.frame_damage().clone());
             }
+            self.timer.end_frame();
+        }
+        Ok(())
+    }
+
+    /// copy `rect` grown by `margin` on every side from one framebuffer to the other
+    unsafe fn copy_region(
+        &self,
+        from: &FboTexture,
+        to: &FboTexture,
+        rect: &win::Rect,
+        margin: i32,
+    ) {
+        let left = (rect.x as i32 - margin).max(0);
+        let right = (rect.x as i32 + rect.width as i32 + margin).min(self.width as i32);
+        // gl framebuffers have y going up
+        let bottom = (self.height as i32 - rect.y as i32 - rect.height as i32 - margin).max(0);
+        let top = (self.height as i32 - rect.y as i32 + margin).min(self.height as i32);
+        if left >= right || bottom >= top {
+            return;
+        }
+        gl::BindFramebuffer(gl::READ_FRAMEBUFFER, from.fbo);
+        gl::BindFramebuffer(gl::DRAW_FRAMEBUFFER, to.fbo);
+        gl::BlitFramebuffer(
+            left,
+            bottom,
+            right,
+            top,
+            left,
+            bottom,
+            right,
+            top,
+            gl::COLOR_BUFFER_BIT,
+            gl::NEAREST,
+        );
+        gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+    }
 
-            gl::UseProgram(self.desc.screen_shader);
-            gl::Uniform1i(self.desc.screen_texture_uniform_handle, 0);
-            gl::ActiveTexture(gl::TEXTURE0);
-            gl::BindTexture(gl::TEXTURE_2D, target.texture);
-            gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
-            gl::DrawElements(gl::TRIANGLES, 6, gl::UNSIGNED_INT, null());
+    /// what window textures are bound to, 2d unless the fb config can't do it
+    /// gl 3.3 always has npot 2d textures, so that's only down to texture_from_pixmap
+    fn texture_target(&self) -> gl::types::GLenum {
+        match &self.platform {
+            GlPlatform::Glx {
+                texture_rectangle: true,
+                ..
+            } => gl::TEXTURE_RECTANGLE,
+            _ => gl::TEXTURE_2D,
+        }
+    }
 
-            glx::SwapBuffers(display, overlay as u64);
+    /// whether the window's texture starts at the top, unlike gl's usual bottom up
+    fn y_inverted(&self, w: &GlWin) -> bool {
+        match (&self.platform, &w.source) {
+            // uploads and egl images of pixmaps keep X's top to bottom rows
+            (_, PixmapSource::Upload(_)) => true,
+            #[cfg(feature = "egl")]
+            (GlPlatform::Egl(_), _) => true,
+            (GlPlatform::Glx { y_inverted, .. }, _) => *y_inverted,
+        }
+    }
+
+    /// copy whatever was drawn to windows that can't be bound into their textures
+    /// the whole window the first time, only the damage after that
+    unsafe fn upload_damaged(
+        &mut self,
+        wins: &win::WinTracker,
+        conn: &impl x11rb::connection::Connection,
+    ) -> Result<(), errors::CompError> {
+        let target = self.texture_target();
+        let mut fetches = vec![];
+        for w in wins.mapped_wins() {
+            let gw = match self.wins.get(&w.handle()) {
+                Some(gw) => gw,
+                None => continue,
+            };
+            let pixmap = match gw.source {
+                PixmapSource::Upload(pixmap) => pixmap,
+                _ => continue,
+            };
+            let size = w.screen_rect();
+            let full = win::Rect::new(0, 0, size.width, size.height);
+            if !gw.bound.get() {
+                // without an alpha channel the alpha is garbage, so it's dropped
+                let format = match w.argb {
+                    true => gl::RGBA,
+                    false => gl::RGB,
+                };
+                gl::BindTexture(target, gw.texture);
+                gl::TexImage2D(
+                    target,
+                    0,
+                    format.try_into()?,
+                    full.width as i32,
+                    full.height as i32,
+                    0,
+                    gl::BGRA,
+                    gl::UNSIGNED_BYTE,
+                    null(),
+                );
+                fetches.push((w.handle(), gw.texture, pixmap, full));
+                gw.bound.set(true);
+                continue;
+            }
+            for r in w.damaged_rects().iter().filter_map(|r| r.intersect(&full)) {
+                fetches.push((w.handle(), gw.texture, pixmap, r));
+            }
+        }
+        if fetches.is_empty() {
+            return Ok(());
+        }
+        let failed = match self.has_shm {
+            true => self.upload_shm(conn, &fetches, target)?,
+            false => upload_get_image(conn, &fetches, target)?,
+        };
+        // unmapped or destroyed since, it's uploaded in full if it's drawn again
+        for handle in failed {
+            if let Some(gw) = self.wins.get(&handle) {
+                gw.bound.set(false);
+            }
         }
         Ok(())
     }
 
-    unsafe fn render_win(
+    /// copy each rect of a pixmap into its texture through the shared memory segment,
+    /// as many as fit in it are requested before waiting on any of them
+    /// returns the windows that couldn't be read
+    unsafe fn upload_shm(
+        &mut self,
+        conn: &impl x11rb::connection::Connection,
+        fetches: &[Fetch],
+        target: gl::types::GLenum,
+    ) -> Result<Vec<Window>, errors::CompError> {
+        // 32 bits per pixel for both 24 and 32 bit windows
+        let len = |r: &win::Rect| r.width as usize * r.height as usize * 4;
+        let largest = fetches.iter().map(|f| len(&f.3)).max().unwrap_or(0);
+        let total: usize = fetches.iter().map(|f| len(&f.3)).sum();
+        // all of a frame's damage in one go, unless that's more than a screen's worth
+        let size = largest.max(total.min(self.width as usize * self.height as usize * 4));
+        if self.shm.as_ref().map_or(true, |s| s.size < size) {
+            if let Some(old) = self.shm.take() {
+                old.detach(conn);
+            }
+            self.shm = Some(ShmSegment::new(conn, size)?);
+        }
+        let shm = self.shm.as_ref().unwrap();
+        let mut failed = vec![];
+        let mut rest = fetches;
+        while !rest.is_empty() {
+            let mut cookies = vec![];
+            let mut offset = 0;
+            for (_, _, pixmap, r) in rest.iter() {
+                if offset + len(r) > shm.size {
+                    break;
+                }
+                let cookie = conn.shm_get_image(
+                    *pixmap,
+                    r.x,
+                    r.y,
+                    r.width,
+                    r.height,
+                    !0,
+                    ImageFormat::Z_PIXMAP.into(),
+                    shm.seg,
+                    offset as u32,
+                )?;
+                cookies.push((offset, cookie));
+                offset += len(r);
+            }
+            let (batch, later) = rest.split_at(cookies.len());
+            for ((handle, texture, _, r), (offset, cookie)) in batch.iter().zip(cookies) {
+                match cookie.reply() {
+                    Ok(_) => {
+                        gl::BindTexture(target, *texture);
+                        upload_rect(
+                            r,
+                            (shm.addr as *const u8).add(offset) as *const c_void,
+                            target,
+                        );
+                    }
+                    Err(ReplyError::X11Error(_)) => failed.push(*handle),
+                    Err(e) => Err(e)?,
+                }
+            }
+            rest = later;
+        }
+        Ok(failed)
+    }
+
+    /// how many frames ago the back buffer was presented, none if it's unknown or it has never been drawn to
+    unsafe fn buffer_age(&self) -> Option<usize> {
+        match &self.platform {
+            GlPlatform::Glx {
+                display,
+                overlay,
+                buffer_age: true,
+                ..
+            } => {
+                let mut age = 0;
+                glx::QueryDrawable(
+                    *display,
+                    *overlay as u64,
+                    glx::BACK_BUFFER_AGE_EXT as i32,
+                    &mut age,
+                );
+                match age {
+                    0 => None,
+                    age => Some(age as usize),
+                }
+            }
+            _ => None,
+        }
+    }
+
+    /// what has to be presented for the back buffer to match the target: `frame_damage`
+    /// plus everything damaged since the back buffer was last shown, none to present in full
+    unsafe fn repaint_region(
         &self,
-        w: &win::Win,
-        display: *mut glx::types::Display,
-        target: &FboTexture,
-        background: &FboTexture,
-    ) {
+        frame_damage: &damage::DamageRegion,
+    ) -> Option<damage::DamageRegion> {
+        // an age of 1 is last frame's buffer, which only misses this frame's damage
+        let missed = self.buffer_age()? - 1;
+        if missed > self.damage_history.len() {
+            return None;
+        }
+        let mut ret = frame_damage.clone();
+        for d in self.damage_history.iter().take(missed) {
+            ret.union(d);
+        }
+        Some(ret)
+    }
+
+    unsafe fn read_target(&self, rect: &win::Rect) -> Result<renderer::Frame, errors::CompError> {
+        let rect = rect
+            .intersect(&win::Rect::new(0, 0, self.width, self.height))
+            .ok_or(format!("{:?} is outside the screen", rect))?;
+        let row_len = rect.width as usize * 4;
+        let mut data = vec![0; row_len * rect.height as usize];
+        gl::BindFramebuffer(gl::READ_FRAMEBUFFER, self.desc.target.fbo);
+        gl::PixelStorei(gl::PACK_ALIGNMENT, 1);
+        // gl's origin is the bottom left of the screen
+        gl::ReadPixels(
+            rect.x as i32,
+            self.height as i32 - rect.y as i32 - rect.height as i32,
+            rect.width as i32,
+            rect.height as i32,
+            gl::RGBA,
+            gl::UNSIGNED_BYTE,
+            data.as_mut_ptr() as *mut c_void,
+        );
+        gl::BindFramebuffer(gl::READ_FRAMEBUFFER, 0);
+        flip_rows(&mut data, row_len);
+        Ok(renderer::Frame {
+            width: rect.width,
+            height: rect.height,
+            data,
+        })
+    }
+
+    /// start copying the finished frame into a pixel buffer, and hand back the oldest copy if it's done
+    unsafe fn queue_readback(&mut self) -> Option<renderer::Frame> {
+        let mut ret = None;
+        if let Some(oldest) = self.readbacks.front() {
+            let status = gl::ClientWaitSync(oldest.fence, 0, 0);
+            if status == gl::ALREADY_SIGNALED
+                || status == gl::CONDITION_SATISFIED
+                || self.readbacks.len() >= READBACK_BUFFERS
+            {
+                let oldest = self.readbacks.pop_front().unwrap();
+                ret = Some(self.finish_readback(oldest));
+            }
+        }
+
+        let pbo = self.free_pbos.pop().unwrap_or_else(|| {
+            let mut pbo = 0;
+            gl::GenBuffers(1, &mut pbo);
+            pbo
+        });
+        gl::BindBuffer(gl::PIXEL_PACK_BUFFER, pbo);
+        gl::BufferData(
+            gl::PIXEL_PACK_BUFFER,
+            self.width as gl::types::GLsizeiptr * self.height as gl::types::GLsizeiptr * 4,
+            null(),
+            gl::STREAM_READ,
+        );
+        gl::BindFramebuffer(gl::READ_FRAMEBUFFER, self.desc.target.fbo);
+        gl::PixelStorei(gl::PACK_ALIGNMENT, 1);
+        // with a pack buffer bound this returns straight away, the pointer is an offset into the buffer
+        gl::ReadPixels(
+            0,
+            0,
+            self.width as i32,
+            self.height as i32,
+            gl::RGBA,
+            gl::UNSIGNED_BYTE,
+            null_mut(),
+        );
+        self.readbacks.push_back(PendingReadback {
+            pbo,
+            fence: gl::FenceSync(gl::SYNC_GPU_COMMANDS_COMPLETE, 0),
+            width: self.width,
+            height: self.height,
+        });
+        gl::BindFramebuffer(gl::READ_FRAMEBUFFER, 0);
+        gl::BindBuffer(gl::PIXEL_PACK_BUFFER, 0);
+        ret
+    }
+
+    /// waits for the gpu if it hasn't finished the copy yet
+    unsafe fn finish_readback(&mut self, readback: PendingReadback) -> renderer::Frame {
+        gl::ClientWaitSync(readback.fence, gl::SYNC_FLUSH_COMMANDS_BIT, u64::MAX);
+        gl::DeleteSync(readback.fence);
+
+        let row_len = readback.width as usize * 4;
+        let len = row_len * readback.height as usize;
+        let mut data = vec![0; len];
+        gl::BindBuffer(gl::PIXEL_PACK_BUFFER, readback.pbo);
+        let mapped = gl::MapBufferRange(
+            gl::PIXEL_PACK_BUFFER,
+            0,
+            len as gl::types::GLsizeiptr,
+            gl::MAP_READ_BIT,
+        );
+        if !mapped.is_null() {
+            std::ptr::copy_nonoverlapping(mapped as *const u8, data.as_mut_ptr(), len);
+            gl::UnmapBuffer(gl::PIXEL_PACK_BUFFER);
+        }
+        gl::BindBuffer(gl::PIXEL_PACK_BUFFER, 0);
+        self.free_pbos.push(readback.pbo);
+
+        flip_rows(&mut data, row_len);
+        renderer::Frame {
+            width: readback.width,
+            height: readback.height,
+            data,
+        }
+    }
+
+    /// damage fading out, and window outlines with their stacking index
+    unsafe fn draw_debug(
+        &self,
+        wins: &win::WinTracker,
+        fbo: gl::types::GLuint,
+    ) -> Result<(), errors::CompError> {
+        if wins.config.effects.debug_damage {
+            for (age, rect) in wins.recent_damage() {
+                let alpha = 0.5 * (1.0 - age);
+                self.draw_rect(rect, [alpha, 0.0, alpha, alpha], fbo);
+            }
+        }
+        if wins.config.effects.debug_boxes {
+            for (i, w) in wins.wins().iter().enumerate().skip(1) {
+                if !w.mapped() {
+                    continue;
+                }
+                let r = w.rect;
+                let (right, bottom) = (r.x + r.width as i16 - 2, r.y + r.height as i16 - 2);
+                let color = [1.0, 1.0, 0.0, 1.0];
+                self.draw_rect(&win::Rect::new(r.x, r.y, r.width, 2), color, fbo);
+                self.draw_rect(&win::Rect::new(r.x, bottom, r.width, 2), color, fbo);
+                self.draw_rect(&win::Rect::new(r.x, r.y, 2, r.height), color, fbo);
+                self.draw_rect(&win::Rect::new(right, r.y, 2, r.height), color, fbo);
+                let label = hud::label(&format!("{} {:#x}", i, w.handle()));
+                self.draw_image(label, r.x as i32 + 2, r.y as i32 + 2, fbo)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// blend a premultiplied `color` over `rect` (in root coordinates)
+    unsafe fn draw_rect(&self, rect: &win::Rect, color: [f32; 4], fbo: gl::types::GLuint) {
+        gl::BindFramebuffer(gl::FRAMEBUFFER, fbo);
+        gl::UseProgram(self.solid_shader);
         gl::Uniform4f(
-            self.desc.win_rect_uniform_handle,
-            w.rect.x as f32,
-            w.rect.y as f32,
-            w.rect.width as f32,
-            w.rect.height as f32,
+            self.solid_color_uniform_handle,
+            color[0],
+            color[1],
+            color[2],
+            color[3],
+        );
+        self.draw_in_viewport(
+            rect.x as i32,
+            rect.y as i32,
+            rect.width as i32,
+            rect.height as i32,
         );
+    }
+
+    /// blend a premultiplied top to bottom image over the screen with its top left corner at `x`, `y`
+    unsafe fn draw_image(
+        &self,
+        mut image: renderer::Frame,
+        x: i32,
+        y: i32,
+        fbo: gl::types::GLuint,
+    ) -> Result<(), errors::CompError> {
+        // textures start at the bottom
+        flip_rows(&mut image.data, image.width as usize * 4);
         gl::ActiveTexture(gl::TEXTURE0);
-        gl::BindTexture(gl::TEXTURE_2D, w.texture);
-        glx::BindTexImageEXT(
-            display as *mut glx::types::Display,
-            w.glx_pixmap,
-            glx::FRONT_EXT as i32,
-            null(),
+        gl::BindTexture(gl::TEXTURE_2D, self.image_texture);
+        gl::TexImage2D(
+            gl::TEXTURE_2D,
+            0,
+            gl::RGBA.try_into()?,
+            image.width as i32,
+            image.height as i32,
+            0,
+            gl::RGBA,
+            gl::UNSIGNED_BYTE,
+            image.data.as_ptr() as *const c_void,
+        );
+
+        gl::BindFramebuffer(gl::FRAMEBUFFER, fbo);
+        gl::UseProgram(self.desc.screen_shader);
+        gl::Uniform1i(self.desc.screen_texture_uniform_handle, 0);
+        self.draw_in_viewport(x, y, image.width as i32, image.height as i32);
+        Ok(())
+    }
+
+    /// the screen quad fills the viewport, so this draws whatever program is bound over a rect
+    /// `x`, `y` is the top left corner, in root coordinates
+    unsafe fn draw_in_viewport(&self, x: i32, y: i32, width: i32, height: i32) {
+        gl::Viewport(x, self.height as i32 - y - height, width, height);
+        gl::Enable(gl::BLEND);
+        gl::BlendFunc(gl::ONE, gl::ONE_MINUS_SRC_ALPHA);
+        self.desc.screen_mesh.draw();
+        gl::Disable(gl::BLEND);
+        gl::Viewport(0, 0, self.width as i32, self.height as i32);
+    }
+
+    /// limit drawing to `rect`, in root coordinates
+    unsafe fn scissor(&self, rect: &win::Rect) {
+        gl::Scissor(
+            rect.x as i32,
+            self.height as i32 - rect.y as i32 - rect.height as i32,
+            rect.width as i32,
+            rect.height as i32,
         );
+    }
 
-        gl::ActiveTexture(gl::TEXTURE1);
+    /// bind the window's texture to `unit`, along with its pixmap if that changed
+    unsafe fn bind_win_texture(&self, w: &GlWin, unit: u32) {
+        gl::ActiveTexture(gl::TEXTURE0 + unit);
+        gl::BindTexture(self.texture_target(), w.texture);
+        if let (GlPlatform::Glx { display, .. }, PixmapSource::Glx(pixmap)) =
+            (&self.platform, &w.source)
+        {
+            // unchanged windows keep what was bound last time
+            if w.stale.get() && w.bound.get() {
+                glx::ReleaseTexImageEXT(*display, *pixmap, glx::FRONT_EXT as i32);
+                w.bound.set(false);
+            }
+            if !w.bound.get() {
+                glx::BindTexImageEXT(*display, *pixmap, glx::FRONT_EXT as i32, null());
+                w.bound.set(true);
+                w.stale.set(false);
+            }
+        }
+    }
+
+    /// the shader's uniforms have to be set already
+    /// a window drawn alone is clipped to `visible`, batched windows only have their visible rects as instances
+    unsafe fn render_batch(
+        &self,
+        batch: &Batch,
+        visible: &damage::DamageRegion,
+        target: &FboTexture,
+        background: &FboTexture,
+    ) {
+        for (i, w) in batch.wins.iter().enumerate() {
+            self.bind_win_texture(w, WIN_TEXTURE_UNIT + i as u32);
+        }
+        gl::ActiveTexture(gl::TEXTURE0 + BG_TEXTURE_UNIT);
         gl::BindTexture(gl::TEXTURE_2D, background.texture);
         gl::BindFramebuffer(gl::FRAMEBUFFER, target.fbo);
 
-        gl::DrawElements(gl::TRIANGLES, 6, gl::UNSIGNED_INT, null());
-        glx::ReleaseTexImageEXT(display, w.glx_pixmap, glx::FRONT_EXT as i32);
+        // the shader outputs premultiplied color with the corner mask as alpha,
+        // so whatever is cut off by the corners keeps the (unblurred) background
+        gl::Enable(gl::BLEND);
+        gl::BlendFunc(gl::ONE, gl::ONE_MINUS_SRC_ALPHA);
+        match batch.alone {
+            // the rects don't overlap, so nothing is blended twice
+            true => {
+                gl::Enable(gl::SCISSOR_TEST);
+                for r in visible.rects() {
+                    self.scissor(r);
+                    self.desc.win_mesh.draw_instances(batch.first, 1);
+                }
+                gl::Disable(gl::SCISSOR_TEST);
+            }
+            // instances are drawn in order, so windows higher up are blended over the ones below
+            false => self.desc.win_mesh.draw_instances(batch.first, batch.count),
+        }
+        gl::Disable(gl::BLEND);
+        gl::ActiveTexture(gl::TEXTURE0);
+    }
+}
+
+impl<C: x11rb::connection::Connection> renderer::Backend<C> for GLRenderer {
+    fn name(&self) -> &'static str {
+        match self.platform {
+            GlPlatform::Glx { .. } => "gl",
+            #[cfg(feature = "egl")]
+            GlPlatform::Egl(_) => "egl",
+        }
+    }
+    fn bind_pixmap(&mut self, win: &win::Win, conn: &C) -> Result<(), errors::CompError> {
+        unsafe { self.bind_win_pixmap(win, conn) }
+    }
+    fn release_pixmap(&mut self, win: &win::Win, _conn: &C) -> Result<(), errors::CompError> {
+        unsafe { self.release_win_pixmap(win.handle()) };
+        Ok(())
+    }
+    fn resize(&mut self, _conn: &C, width: u16, height: u16) -> Result<(), errors::CompError> {
+        self.resize_screen(width, height)
+    }
+    fn render(&mut self, wins: &win::WinTracker, conn: &C) -> Result<(), errors::CompError> {
+        unsafe { self.upload_damaged(wins, conn)? };
+        self.render_frame(wins)
     }
+    fn read_pixels(
+        &mut self,
+        _conn: &C,
+        rect: &win::Rect,
+    ) -> Result<renderer::Frame, errors::CompError> {
+        unsafe { self.read_target(rect) }
+    }
+    fn read_frame_async(
+        &mut self,
+        _conn: &C,
+    ) -> Result<Option<renderer::Frame>, errors::CompError> {
+        Ok(unsafe { self.queue_readback() })
+    }
+    fn gpu_times(&mut self) -> Option<Vec<(&'static str, f32)>> {
+        unsafe { self.timer.collect() }
+    }
+    fn vsync(&self) -> bool {
+        match &self.platform {
+            _ if self.headless => false,
+            GlPlatform::Glx { swap_control, .. } => *swap_control,
+            #[cfg(feature = "egl")]
+            GlPlatform::Egl(_) => true,
+        }
+    }
+    fn reload_shaders(&mut self) -> Result<(), errors::CompError> {
+        self.desc
+            .reload_win_shader(DEFAULT_WIN_VS_PATH, DEFAULT_WIN_FS_PATH)
+    }
+}
+
+/// turn bottom to top rows (what gl reads back) into top to bottom, or the other way around
+pub(crate) fn flip_rows(data: &mut [u8], row_len: usize) {
+    let rows = data.len() / row_len;
+    for i in 0..rows / 2 {
+        let (top, bottom) = data.split_at_mut((rows - 1 - i) * row_len);
+        top[i * row_len..(i + 1) * row_len].swap_with_slice(&mut bottom[..row_len]);
+    }
+}
+
+/// copy each rect of a pixmap into its texture with GetImage,
+/// all of them are requested before waiting on any
+/// returns the windows that couldn't be read
+unsafe fn upload_get_image(
+    conn: &impl x11rb::connection::Connection,
+    fetches: &[Fetch],
+    target: gl::types::GLenum,
+) -> Result<Vec<Window>, errors::CompError> {
+    let mut cookies = vec![];
+    for (_, _, pixmap, r) in fetches.iter() {
+        cookies.push(conn.get_image(
+            ImageFormat::Z_PIXMAP,
+            *pixmap,
+            r.x,
+            r.y,
+            r.width,
+            r.height,
+            !0,
+        )?);
+    }
+    let mut failed = vec![];
+    for ((handle, texture, _, r), cookie) in fetches.iter().zip(cookies) {
+        match cookie.reply() {
+            Ok(image) => {
+                gl::BindTexture(target, *texture);
+                upload_rect(r, image.data.as_ptr() as *const c_void, target);
+            }
+            Err(ReplyError::X11Error(_)) => failed.push(*handle),
+            Err(e) => Err(e)?,
+        }
+    }
+    Ok(failed)
+}
+
+/// `data` is the top to bottom, 32 bits per pixel contents of `rect` of a pixmap,
+/// which goes in the same place in the texture bound to `target`
+unsafe fn upload_rect(rect: &win::Rect, data: *const c_void, target: gl::types::GLenum) {
+    gl::PixelStorei(gl::UNPACK_ALIGNMENT, 4);
+    gl::TexSubImage2D(
+        target,
+        0,
+        rect.x as i32,
+        rect.y as i32,
+        rect.width as i32,
+        rect.height as i32,
+        gl::BGRA,
+        gl::UNSIGNED_BYTE,
+        data,
+    );
 }
 
 unsafe fn clear_fbo(fbo: u32) {
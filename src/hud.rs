@@ -0,0 +1,172 @@
+use crate::renderer;
+use crate::stats;
+
+// everything is drawn at twice the font's size
+const SCALE: usize = 2;
+const GLYPH_WIDTH: usize = 3;
+const GLYPH_HEIGHT: usize = 5;
+const LINE_HEIGHT: usize = (GLYPH_HEIGHT + 2) * SCALE;
+const PADDING: usize = 6;
+const LINES: usize = 4;
+const GRAPH_HEIGHT: usize = 48;
+
+const WIDTH: usize = stats::HISTORY_LEN + PADDING * 2;
+const HEIGHT: usize = PADDING * 3 + LINES * LINE_HEIGHT + GRAPH_HEIGHT;
+
+// premultiplied rgba
+const BACKGROUND: [u8; 4] = [0, 0, 0, 0xb0];
+const TEXT: [u8; 4] = [0xff, 0xff, 0xff, 0xff];
+const BAR_OK: [u8; 4] = [0x40, 0xd0, 0x40, 0xff];
+const BAR_SLOW: [u8; 4] = [0xe0, 0x40, 0x30, 0xff];
+const TARGET_LINE: [u8; 4] = [0x80, 0x80, 0x80, 0xff];
+
+/// a 3x5 pixel font, each row is 3 bits with the leftmost pixel the highest bit
+fn glyph(c: char) -> [u8; GLYPH_HEIGHT] {
+    match c.to_ascii_uppercase() {
+        '0' | 'O' => [7, 5, 5, 5, 7],
+        '1' => [2, 6, 2, 2, 7],
+        '2' => [7, 1, 7, 4, 7],
+        '3' => [7, 1, 7, 1, 7],
+        '4' => [5, 5, 7, 1, 1],
+        '5' | 'S' => [7, 4, 7, 1, 7],
+        '6' => [7, 4, 7, 5, 7],
+        '7' => [7, 1, 1, 1, 1],
+        '8' => [7, 5, 7, 5, 7],
+        '9' => [7, 5, 7, 1, 7],
+        '.' => [0, 0, 0, 0, 2],
+        ':' => [0, 2, 0, 2, 0],
+        '/' => [1, 1, 2, 4, 4],
+        'A' => [2, 5, 7, 5, 5],
+        'B' => [6, 5, 6, 5, 6],
+        'C' => [7, 4, 4, 4, 7],
+        'D' => [6, 5, 5, 5, 6],
+        'E' => [7, 4, 6, 4, 7],
+        'F' => [7, 4, 6, 4, 4],
+        'G' => [7, 4, 5, 5, 7],
+        'H' => [5, 5, 7, 5, 5],
+        'I' => [7, 2, 2, 2, 7],
+        'J' => [1, 1, 1, 5, 7],
+        'K' => [5, 5, 6, 5, 5],
+        'L' => [4, 4, 4, 4, 7],
+        'M' => [5, 7, 7, 5, 5],
+        'N' => [6, 5, 5, 5, 5],
+        'P' => [7, 5, 7, 4, 4],
+        'Q' => [7, 5, 5, 7, 1],
+        'R' => [6, 5, 6, 5, 5],
+        'T' => [7, 2, 2, 2, 2],
+        'U' => [5, 5, 5, 5, 7],
+        'V' => [5, 5, 5, 5, 2],
+        'W' => [5, 5, 7, 7, 5],
+        'X' => [5, 5, 2, 5, 5],
+        'Y' => [5, 5, 2, 2, 2],
+        'Z' => [7, 1, 2, 4, 7],
+        _ => [0; GLYPH_HEIGHT],
+    }
+}
+
+struct Canvas {
+    width: usize,
+    height: usize,
+    data: Vec<u8>,
+}
+
+impl Canvas {
+    fn new(width: usize, height: usize) -> Canvas {
+        Canvas {
+            width,
+            height,
+            data: BACKGROUND.repeat(width * height),
+        }
+    }
+
+    fn fill(&mut self, x: usize, y: usize, width: usize, height: usize, color: [u8; 4]) {
+        for row in y..(y + height).min(self.height) {
+            for col in x..(x + width).min(self.width) {
+                let i = (row * self.width + col) * 4;
+                self.data[i..i + 4].copy_from_slice(&color);
+            }
+        }
+    }
+
+    fn text(&mut self, x: usize, y: usize, s: &str) {
+        for (i, c) in s.chars().enumerate() {
+            let left = x + i * (GLYPH_WIDTH + 1) * SCALE;
+            for (row, bits) in glyph(c).iter().enumerate() {
+                for col in 0..GLYPH_WIDTH {
+                    if bits & (1 << (GLYPH_WIDTH - 1 - col)) != 0 {
+                        self.fill(left + col * SCALE, y + row * SCALE, SCALE, SCALE, TEXT);
+                    }
+                }
+            }
+        }
+    }
+
+    fn into_frame(self) -> renderer::Frame {
+        renderer::Frame {
+            width: self.width as u16,
+            height: self.height as u16,
+            data: self.data,
+        }
+    }
+}
+
+/// one line of text on the hud background, sized to fit
+pub fn label(text: &str) -> renderer::Frame {
+    // no gap after the last glyph
+    let width = (text.chars().count() * (GLYPH_WIDTH + 1) * SCALE + 4).saturating_sub(SCALE);
+    let mut canvas = Canvas::new(width.max(4), GLYPH_HEIGHT * SCALE + 4);
+    canvas.text(2, 2, text);
+    canvas.into_frame()
+}
+
+/// the hud as an image, drawn in the top left corner of the screen by the renderer
+pub fn draw(stats: &stats::FrameStats, wins: usize, refresh_rate: f32) -> renderer::Frame {
+    let mut canvas = Canvas::new(WIDTH, HEIGHT);
+    let lines = [
+        format!("FPS {:.1}  CPU {:.2}MS", stats.fps(), stats.cpu.mean()),
+        format!(
+            "FRAME {:.2}MS  MAX {:.2}",
+            stats.present_interval.last(),
+            stats.present_interval.max()
+        ),
+        format!("GPU {:.2}MS", stats.gpu.mean()),
+        format!(
+            "WINS {}  DMG {}K PX",
+            wins,
+            (stats.damage.last() / 1000.0).round()
+        ),
+    ];
+    for (i, line) in lines.iter().enumerate() {
+        canvas.text(PADDING, PADDING + i * LINE_HEIGHT, line);
+    }
+
+    // present intervals, the full height is two frames at the target rate
+    let target = 1000.0 / refresh_rate.max(1.0);
+    let graph_top = PADDING * 2 + LINES * LINE_HEIGHT;
+    let scale = GRAPH_HEIGHT as f32 / (target * 2.0);
+    let skip = stats::HISTORY_LEN - stats.present_interval.iter().count();
+    for (i, t) in stats.present_interval.iter().enumerate() {
+        let height = ((t * scale).round() as usize).min(GRAPH_HEIGHT);
+        // a little slack so vsync jitter doesn't flash red
+        let color = match t > target * 1.2 {
+            true => BAR_SLOW,
+            false => BAR_OK,
+        };
+        canvas.fill(
+            PADDING + skip + i,
+            graph_top + GRAPH_HEIGHT - height,
+            1,
+            height,
+            color,
+        );
+    }
+    canvas.fill(
+        PADDING,
+        graph_top + GRAPH_HEIGHT / 2,
+        stats::HISTORY_LEN,
+        1,
+        TARGET_LINE,
+    );
+
+    canvas.into_frame()
+}
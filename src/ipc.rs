@@ -0,0 +1,344 @@
+use crate::errors;
+use crate::renderer;
+use crate::screenshot;
+use crate::socket_path;
+use crate::stats;
+use crate::win;
+
+use std::io::{ErrorKind, Read, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use x11rb::protocol::xproto::Window;
+
+/// the last bucket of timing histograms has everything slower than this many ms
+const HISTOGRAM_BUCKETS: usize = 34;
+/// a client with more replies than this waiting to go out isn't reading them, so it's dropped
+const MAX_PENDING_REPLY_BYTES: usize = 1 << 20;
+
+/// one json object per line, answered with one json object per line
+/// every reply has `"ok"`, and `"error"` when it's false
+#[derive(Debug, Deserialize)]
+#[serde(tag = "command", rename_all = "kebab-case")]
+enum Request {
+    ListWindows,
+    /// `window` can be the frame or the client, an empty `shader` goes back to the default
+    SetWindow {
+        window: Window,
+        opacity: Option<f32>,
+        shader: Option<String>,
+    },
+    SetEffect {
+        effect: String,
+        enabled: bool,
+    },
+    ReloadConfig,
+    ReloadShaders,
+    /// taken after the next frame, `target` is as for --screenshot
+    Screenshot {
+        target: String,
+        path: String,
+    },
+    Stats,
+}
+
+#[derive(Debug, Serialize)]
+struct WinInfo {
+    handle: Window,
+    client: Option<Window>,
+    x: i16,
+    y: i16,
+    width: u16,
+    height: u16,
+    mapped: bool,
+    opacity: f32,
+    shader: Option<String>,
+    /// 0 is the root, higher is on top
+    stacking: usize,
+}
+
+#[derive(Debug)]
+struct Client {
+    stream: UnixStream,
+    /// what's been read that doesn't end in a newline yet
+    buf: Vec<u8>,
+    /// replies that didn't fit in the socket yet
+    out: Vec<u8>,
+}
+
+/// a control socket, polled once per loop so requests are handled between frames
+#[derive(Debug)]
+pub struct IpcServer {
+    listener: UnixListener,
+    path: PathBuf,
+    clients: Vec<Client>,
+    /// for reload-config
+    config_path: String,
+}
+
+impl IpcServer {
+    /// `path` is the default for the display if `None`
+    pub fn new(path: Option<&str>, config_path: &str) -> Result<IpcServer, errors::CompError> {
+        let path = path
+            .map(PathBuf::from)
+            .unwrap_or_else(socket_path::default_socket_path);
+        // left behind if we didn't exit cleanly, binding fails if it's still there
+        if path.exists() {
+            if UnixStream::connect(&path).is_ok() {
+                Err(format!(
+                    "{} is in use, is another xcomprs running?",
+                    path.display()
+                ))?
+            }
+            std::fs::remove_file(&path)?;
+        }
+        let listener = UnixListener::bind(&path)?;
+        listener.set_nonblocking(true)?;
+        println!("listening on {}", path.display());
+        Ok(IpcServer {
+            listener,
+            path,
+            clients: vec![],
+            config_path: config_path.to_string(),
+        })
+    }
+
+    /// accept new clients and answer every complete request, never blocks
+    pub fn poll<C: x11rb::connection::Connection>(
+        &mut self,
+        tracker: &mut win::WinTracker,
+        backend: &mut dyn renderer::Backend<C>,
+    ) -> Result<(), errors::CompError> {
+        loop {
+            match self.listener.accept() {
+                Ok((stream, _)) => {
+                    stream.set_nonblocking(true)?;
+                    self.clients.push(Client {
+                        stream,
+                        buf: vec![],
+                        out: vec![],
+                    });
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) => Err(e)?,
+            }
+        }
+
+        let mut i = 0;
+        while i < self.clients.len() {
+            // a client going away (or misbehaving) is its own problem
+            match self.poll_client(i, tracker, backend) {
+                Ok(true) => i += 1,
+                Ok(false) => {
+                    self.clients.remove(i);
+                }
+                Err(e) => {
+                    println!("warning: dropping ipc client: {:?}", e);
+                    self.clients.remove(i);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// false once the client has hung up
+    fn poll_client<C: x11rb::connection::Connection>(
+        &mut self,
+        i: usize,
+        tracker: &mut win::WinTracker,
+        backend: &mut dyn renderer::Backend<C>,
+    ) -> Result<bool, errors::CompError> {
+        let mut open = true;
+        let mut chunk = [0; 4096];
+        loop {
+            match self.clients[i].stream.read(&mut chunk) {
+                Ok(0) => {
+                    open = false;
+                    break;
+                }
+                Ok(n) => self.clients[i].buf.extend_from_slice(&chunk[..n]),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => Err(e)?,
+            }
+        }
+
+        while let Some(end) = self.clients[i].buf.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = self.clients[i].buf.drain(..=end).collect();
+            let line = std::str::from_utf8(&line)?.trim();
+            if line.is_empty() {
+                continue;
+            }
+            let reply = match serde_json::from_str::<Request>(line) {
+                Ok(req) => self.handle(req, tracker, backend),
+                Err(e) => Err(format!("bad request: {}", e).into()),
+            };
+            let reply = match reply {
+                Ok(mut v) => {
+                    v["ok"] = json!(true);
+                    v
+                }
+                Err(errors::CompError::Str(e)) => json!({ "ok": false, "error": e }),
+                Err(e) => json!({ "ok": false, "error": format!("{:?}", e) }),
+            };
+            writeln!(self.clients[i].out, "{}", reply)?;
+        }
+
+        // whatever the socket won't take now is sent next time round
+        let client = &mut self.clients[i];
+        while !client.out.is_empty() {
+            match client.stream.write(&client.out) {
+                Ok(0) => {
+                    open = false;
+                    break;
+                }
+                Ok(n) => {
+                    client.out.drain(..n);
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => Err(e)?,
+            }
+        }
+        if client.out.len() > MAX_PENDING_REPLY_BYTES {
+            Err(format!("{} bytes of replies not read", client.out.len()))?
+        }
+        Ok(open)
+    }
+
+    fn handle<C: x11rb::connection::Connection>(
+        &self,
+        req: Request,
+        tracker: &mut win::WinTracker,
+        backend: &mut dyn renderer::Backend<C>,
+    ) -> Result<serde_json::Value, errors::CompError> {
+        match req {
+            Request::ListWindows => {
+                let wins: Vec<WinInfo> = tracker
+                    .wins()
+                    .iter()
+                    .enumerate()
+                    .map(|(i, w)| WinInfo {
+                        handle: w.handle(),
+                        client: w.client,
+                        x: w.rect.x,
+                        y: w.rect.y,
+                        width: w.rect.width,
+                        height: w.rect.height,
+                        mapped: w.mapped(),
+                        opacity: w.opacity,
+                        shader: w.shader.clone(),
+                        stacking: i,
+                    })
+                    .collect();
+                Ok(json!({ "windows": wins }))
+            }
+            Request::SetWindow {
+                window,
+                opacity,
+                shader,
+            } => {
+                let w = tracker
+                    .find_win_mut(window)
+                    .ok_or(format!("no window {:#x}", window))?;
+                if let Some(opacity) = opacity {
+                    if !(0.0..=1.0).contains(&opacity) {
+                        Err(format!("opacity must be between 0 and 1, got {}", opacity))?
+                    }
+                    w.opacity = opacity;
+                }
+                if let Some(shader) = shader {
+                    w.shader = match shader.is_empty() {
+                        true => None,
+                        false => Some(shader),
+                    };
+                }
+                let rect = w.screen_rect();
+                tracker.damage_rect(rect);
+                Ok(json!({}))
+            }
+            Request::SetEffect { effect, enabled } => {
+                tracker.config.effects.set(&effect, enabled)?;
+                tracker.apply_rules();
+                Ok(json!({}))
+            }
+            Request::ReloadConfig => {
+                tracker.config = tracker.config.reload(&self.config_path)?;
+                tracker.apply_rules();
+                println!("config reloaded from {}", self.config_path);
+                Ok(json!({}))
+            }
+            Request::ReloadShaders => {
+                backend.reload_shaders()?;
+                tracker.damage_screen();
+                Ok(json!({}))
+            }
+            Request::Screenshot { target, path } => {
+                tracker.request_screenshot(screenshot::Request {
+                    target: screenshot::Target::parse(&target)?,
+                    path,
+                });
+                Ok(json!({}))
+            }
+            Request::Stats => {
+                let monitors: Vec<serde_json::Value> = tracker
+                    .monitors
+                    .iter()
+                    .map(|m| {
+                        json!({
+                            "name": m.name,
+                            "x": m.rect.x,
+                            "y": m.rect.y,
+                            "width": m.rect.width,
+                            "height": m.rect.height,
+                            "refresh_rate": m.refresh_rate,
+                            "primary": m.primary,
+                        })
+                    })
+                    .collect();
+                Ok(json!({
+                    "backend": backend.name(),
+                    "frame_time_ms": tracker.pacer.frame_time().as_secs_f64() * 1000.0,
+                    "refresh_rate": tracker.pacer.refresh_rate(),
+                    "active_window": tracker.active(),
+                    // not counting the root
+                    "windows": tracker.wins().len() - 1,
+                    "mapped_windows": tracker.wins()[1..].iter().filter(|w| w.mapped()).count(),
+                    "monitors": monitors,
+                    "fps": tracker.stats.fps(),
+                    "cpu_ms": history_json(&tracker.stats.cpu),
+                    "gpu_ms": history_json(&tracker.stats.gpu),
+                    "gpu_pass_ms": tracker
+                        .stats
+                        .gpu_passes
+                        .iter()
+                        .map(|(name, h)| (name.to_string(), history_json(h)))
+                        .collect::<serde_json::Map<String, serde_json::Value>>(),
+                    "present_interval_ms": history_json(&tracker.stats.present_interval),
+                    "damage_px": {
+                        "last": tracker.stats.damage.last(),
+                        "mean": tracker.stats.damage.mean(),
+                    },
+                }))
+            }
+        }
+    }
+}
+
+impl Drop for IpcServer {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// summary of the last few seconds of a timing, with a histogram in 1ms buckets
+fn history_json(h: &stats::History) -> serde_json::Value {
+    json!({
+        "mean": h.mean(),
+        "p99": h.percentile(0.99),
+        "max": h.max(),
+        "histogram": h.histogram(1.0, HISTOGRAM_BUCKETS),
+    })
+}
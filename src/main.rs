@@ -1,13 +1,33 @@
 #![feature(destructuring_assignment)]
 
+mod blur;
+mod config;
+mod damage;
+#[cfg(feature = "dbus")]
+mod dbus_service;
+#[cfg(feature = "egl")]
+mod egl;
+#[cfg(feature = "egl")]
+mod egl_context;
 mod errors;
 mod ewm;
+mod geometry;
 mod gl;
 mod gl_renderer;
 mod glx;
 mod glx_util;
+mod hud;
+mod ipc;
+mod monitor;
+mod pacing;
+mod recorder;
+mod renderer;
+mod screenshot;
+mod socket_path;
+mod stats;
 mod win;
 mod xlib;
+mod xrender_renderer;
 
 use std::ffi::{c_void, CStr, CString};
 
@@ -16,10 +36,12 @@ use std::ptr::null_mut;
 use x11rb::connection::{Connection, RequestConnection};
 use x11rb::protocol::composite::ConnectionExt as composite_ConnectionExt;
 use x11rb::protocol::damage::ConnectionExt as damage_ConnectionExt;
+use x11rb::protocol::randr::{ConnectionExt as randr_ConnectionExt, NotifyMask};
+use x11rb::protocol::render::ConnectionExt as render_ConnectionExt;
 use x11rb::protocol::shape::{ConnectionExt as shape_ConnectionExt, SK};
 use x11rb::protocol::xfixes::{ConnectionExt as xfixes_ConnectionExt, Region};
 use x11rb::protocol::xproto::{
-    ChangeWindowAttributesAux, ConnectionExt as xproto_ConnectionExt, EventMask, Rectangle,
+    ChangeWindowAttributesAux, ConnectionExt as xproto_ConnectionExt, EventMask, Rectangle, Window,
 };
 
 use x11rb::xcb_ffi::XCBConnection;
@@ -36,13 +58,16 @@ const CONTEXT_ATTRS: [i32; 5] = [
 
 // TODO: search through visuals properly
 const NUM_FB_ATTRS: usize = 13;
+/// the attributes at the start of `FB_ATTRS` that need texture_from_pixmap
+const NUM_TFP_FB_ATTRS: usize = 2;
 #[rustfmt::skip]
 const FB_ATTRS: [i32; NUM_FB_ATTRS * 2 + 1] = [
     glx::BIND_TO_TEXTURE_RGB_EXT as i32,
     true as i32,
 
+    // swapped for the rectangle bit if no config can bind to 2d textures
     glx::BIND_TO_TEXTURE_TARGETS_EXT as i32,
-    glx::TEXTURE_2D_EXT as i32,
+    glx::TEXTURE_2D_BIT_EXT as i32,
 
     // glx::Y_INVERTED_EXT as i32,
     // glx::DONT_CARE as i32,
@@ -80,7 +105,247 @@ const FB_ATTRS: [i32; NUM_FB_ATTRS * 2 + 1] = [
     xlib::None as i32,
 ];
 
+/// a GLX context on the overlay, made current
+unsafe fn create_glx_platform(
+    conn: &XCBConnection,
+    display: *mut xlib::Display,
+    screen_num: usize,
+    overlay: Window,
+) -> Result<gl_renderer::GlPlatform, CompError> {
+    if conn.extension_information("GLX")?.is_none() {
+        Err("missing extension 'GLX'".to_string())?
+    }
+    let mut maj: i32 = 0;
+    let mut min: i32 = 0;
+    let has_glx = glx::QueryVersion(display as *mut glx::types::Display, &mut maj, &mut min);
+    if has_glx == gl::FALSE as i32 {
+        Err("GLX not supported".to_string())?
+    }
+    println!("GLX V{}.{}", maj, min);
+    let extensions =
+        glx::QueryExtensionsString(display as *mut glx::types::Display, screen_num as i32);
+    let extensions = match extensions.is_null() {
+        true => String::new(),
+        false => CStr::from_ptr(extensions).to_string_lossy().into_owned(),
+    };
+    let has_extension = |name: &str| extensions.split(' ').any(|e| e == name);
+
+    let choose_fb_config = |attrs: &[i32]| {
+        let mut num_configs: i32 = 0;
+        let fb_configs = glx::ChooseFBConfig(
+            display as *mut glx::types::Display,
+            screen_num as i32,
+            attrs.as_ptr(),
+            &mut num_configs,
+        );
+        match (fb_configs.is_null(), num_configs) {
+            (true, _) | (_, 0) => None,
+            (false, _) => Some(fb_configs),
+        }
+    };
+    // without texture_from_pixmap (or a config that can use it), window contents are copied in instead
+    let mut texture_from_pixmap = has_extension("GLX_EXT_texture_from_pixmap");
+    let fb_configs = match texture_from_pixmap {
+        true => choose_fb_config(&FB_ATTRS).or_else(|| {
+            let mut attrs = FB_ATTRS;
+            attrs[3] = glx::TEXTURE_RECTANGLE_BIT_EXT as i32;
+            choose_fb_config(&attrs)
+        }),
+        false => None,
+    };
+    let fb_configs = match fb_configs {
+        Some(c) => c,
+        None => {
+            println!("GLX_EXT_texture_from_pixmap is unusable, window contents will be uploaded");
+            texture_from_pixmap = false;
+            choose_fb_config(&FB_ATTRS[NUM_TFP_FB_ATTRS * 2..])
+                .ok_or("no matching configs found".to_string())?
+        }
+    };
+    let fb_config = *fb_configs.offset(0);
+    let mut visual_id: i32 = 0;
+    glx::GetFBConfigAttrib(
+        display as *mut glx::types::Display,
+        fb_config,
+        glx::VISUAL_ID as i32,
+        &mut visual_id,
+    );
+    println!("visual_id: {}", visual_id);
+    let mut y_inverted: i32 = 0;
+    let y_inverted = match glx::GetFBConfigAttrib(
+        display as *mut glx::types::Display,
+        fb_config,
+        glx::Y_INVERTED_EXT as i32,
+        &mut y_inverted,
+    ) {
+        // what most drivers do, pixmaps are top to bottom like in X
+        0 => y_inverted != 0,
+        _ => true,
+    };
+    println!("y_inverted: {}", y_inverted);
+    let mut targets: i32 = 0;
+    glx::GetFBConfigAttrib(
+        display as *mut glx::types::Display,
+        fb_config,
+        glx::BIND_TO_TEXTURE_TARGETS_EXT as i32,
+        &mut targets,
+    );
+    let texture_rectangle = texture_from_pixmap && targets & glx::TEXTURE_2D_BIT_EXT as i32 == 0;
+    if texture_rectangle {
+        println!("pixmaps can't be bound as 2d textures, using rectangle textures");
+    }
+    let glx_ctx = glx::CreateContextAttribsARB(
+        display as *mut glx::types::Display,
+        fb_config as *const c_void,
+        null_mut(),
+        true as i32,
+        &CONTEXT_ATTRS as *const i32,
+    );
+    if glx_ctx.is_null() {
+        Err("unable to create context".to_string())?
+    }
+
+    let context_success =
+        glx::MakeCurrent(display as *mut glx::types::Display, overlay as u64, glx_ctx);
+    if context_success == 0 {
+        Err("unable to make context current".to_string())?
+    }
+    // swaps wait for vblank so nothing tears, the pacer only decides when frames are started
+    let swap_control = has_extension("GLX_EXT_swap_control");
+    match swap_control {
+        true => glx::SwapIntervalEXT(display as *mut glx::types::Display, overlay as u64, 1),
+        false => println!("GLX_EXT_swap_control not supported, leaving the swap interval alone"),
+    }
+    gl::load_with(|s| {
+        let c_str = CString::new(s).unwrap();
+        glx::GetProcAddress(c_str.as_ptr() as *const u8) as *const _
+    });
+    let buffer_age = has_extension("GLX_EXT_buffer_age");
+    if !buffer_age {
+        println!("GLX_EXT_buffer_age not supported, every frame will be presented in full");
+    }
+    Ok(gl_renderer::GlPlatform::Glx {
+        display: display as *mut glx::types::Display,
+        fb_config: fb_config as *const c_void,
+        overlay,
+        buffer_age,
+        y_inverted,
+        swap_control,
+        texture_from_pixmap,
+        texture_rectangle,
+    })
+}
+
+/// everything needed to render with gl: a context on the overlay from GLX or EGL (`kind`) and the shaders
+unsafe fn create_gl_renderer(
+    conn: &XCBConnection,
+    display: *mut xlib::Display,
+    screen_num: usize,
+    overlay: Window,
+    kind: renderer::BackendKind,
+    config: &config::Config,
+    width: u16,
+    height: u16,
+) -> Result<gl_renderer::GLRenderer, CompError> {
+    let platform = match kind {
+        #[cfg(feature = "egl")]
+        renderer::BackendKind::Egl => gl_renderer::GlPlatform::Egl(egl_context::EglContext::new(
+            conn,
+            display,
+            screen_num,
+            overlay,
+            // the overlay always has the root visual
+            conn.setup().roots[screen_num].root_visual,
+        )?),
+        #[cfg(not(feature = "egl"))]
+        renderer::BackendKind::Egl => Err("built without the egl feature".to_string())?,
+        _ => create_glx_platform(conn, display, screen_num, overlay)?,
+    };
+    println!(
+        "GL Version: {}",
+        CStr::from_ptr(gl::GetString(gl::VERSION) as *const i8).to_str()?
+    );
+
+    let desc = gl_renderer::WindowDrawDesc::new_shader_paths(
+        None,
+        gl_renderer::DEFAULT_WIN_VS_PATH,
+        gl_renderer::DEFAULT_WIN_FS_PATH,
+        gl_renderer::SCREEN_VS_PATH,
+        gl_renderer::SCREEN_FS_PATH,
+        width,
+        height,
+    )?;
+    let blur = blur::KawaseBlur::new_shader_paths(
+        gl_renderer::SCREEN_VS_PATH,
+        "./shaders/blur_down_fs.glsl",
+        "./shaders/blur_up_fs.glsl",
+        width,
+        height,
+        config.blur.passes,
+    )?;
+    gl_renderer::GLRenderer::new(conn, platform, desc, blur, width, height, config.headless)
+}
+
 pub fn main() {
+    let mut config_path = config::DEFAULT_CONFIG_PATH.to_string();
+    let mut backend = None;
+    let mut headless = false;
+    let mut dump_frame = None;
+    let mut screenshots = vec![];
+    let mut record = None;
+    let mut socket = None;
+    let mut dbus = false;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--config" => {
+                config_path = args.next().expect("--config needs a path");
+            }
+            "--backend" => {
+                backend = Some(
+                    renderer::BackendKind::parse(&args.next().expect("--backend needs a name"))
+                        .expect("invalid backend"),
+                );
+            }
+            "--headless" => headless = true,
+            "--dbus" => dbus = true,
+            "--socket" => {
+                socket = Some(args.next().expect("--socket needs a path"));
+            }
+            "--record" => {
+                record = Some(args.next().expect("--record needs a path"));
+            }
+            // taken after the first frame, e.g. for checking the output in tests
+            "--screenshot" => {
+                let target = screenshot::Target::parse(
+                    &args.next().expect("--screenshot needs a target and a path"),
+                )
+                .expect("invalid screenshot target");
+                let path = args.next().expect("--screenshot needs a target and a path");
+                screenshots.push(screenshot::Request { target, path });
+            }
+            "--dump-frame" => {
+                dump_frame = Some(args.next().expect("--dump-frame needs a path"));
+            }
+            _ => panic!("unknown argument '{}'", arg),
+        }
+    }
+    let mut config = config::Config::load_or_default(&config_path).expect("unable to load config");
+    if let Some(b) = backend {
+        config.backend = b;
+    }
+    config.headless |= headless;
+    if dump_frame.is_some() {
+        config.dump_frame = dump_frame;
+    }
+    if record.is_some() {
+        config.record.path = record;
+    }
+    if socket.is_some() {
+        config.ipc_socket = socket;
+    }
+    config.dbus |= dbus;
+
     let display = unsafe { xlib::XOpenDisplay(null_mut()) };
     if display.is_null() {
         panic!("unable to open display!");
@@ -102,7 +367,7 @@ pub fn main() {
     let height = conn.setup().roots[screen_num].height_in_pixels;
     println!("root: {}", root);
     println!("width, height: {}, {}", width, height);
-    let extensions = vec!["RENDER", "Composite", "DAMAGE", "XFIXES", "SHAPE", "GLX"];
+    let extensions = vec!["RENDER", "Composite", "DAMAGE", "XFIXES", "SHAPE", "RANDR"];
     for ext in extensions.iter() {
         match conn.extension_information(ext).unwrap() {
             Some(_) => (),
@@ -146,53 +411,24 @@ pub fn main() {
         "Xshape V{}.{}",
         xshape_ver_reply.major_version, xshape_ver_reply.minor_version
     );
-
-    let mut maj: i32 = 0;
-    let mut min: i32 = 0;
-    let has_glx =
-        unsafe { glx::QueryVersion(display as *mut glx::types::Display, &mut maj, &mut min) };
-    if has_glx == gl::FALSE as i32 {
-        panic!("GLX not supported");
-    }
-    println!("GLX V{}.{}", maj, min);
-
-    let mut num_configs: i32 = 0;
-    let fb_configs = unsafe {
-        glx::ChooseFBConfig(
-            display as *mut glx::types::Display,
-            screen_num as i32,
-            &FB_ATTRS as *const i32,
-            &mut num_configs,
-        )
-    };
-    match (fb_configs.is_null(), num_configs) {
-        (true, _) => panic!("unable to get fb_configs"),
-        (_, 0) => panic!("no matching configs found"),
-        (false, _) => (),
-    }
-    let fb_config = unsafe { *fb_configs.offset(0) };
-    let mut visual_id: i32 = 0;
-    unsafe {
-        glx::GetFBConfigAttrib(
-            display as *mut glx::types::Display,
-            fb_config,
-            glx::VISUAL_ID as i32,
-            &mut visual_id,
-        )
-    };
-    println!("visual_id: {}", visual_id);
-    let glx_ctx = unsafe {
-        glx::CreateContextAttribsARB(
-            display as *mut glx::types::Display,
-            fb_config as *const c_void,
-            null_mut(),
-            true as i32,
-            &CONTEXT_ATTRS as *const i32,
-        )
-    };
-    if glx_ctx.is_null() {
-        panic!("unable to create context");
-    }
+    let randr_ver_reply = conn
+        .randr_query_version(1, 5)
+        .expect("could not connect to server")
+        .reply()
+        .expect("could not query randr version");
+    println!(
+        "RandR V{}.{}",
+        randr_ver_reply.major_version, randr_ver_reply.minor_version
+    );
+    let render_ver_reply = conn
+        .render_query_version(0, 11)
+        .expect("could not connect to server")
+        .reply()
+        .expect("could not query render version");
+    println!(
+        "Render V{}.{}",
+        render_ver_reply.major_version, render_ver_reply.minor_version
+    );
 
     let overlay = conn
         .composite_get_overlay_window(root)
@@ -240,56 +476,56 @@ pub fn main() {
         .check()
         .expect("unable to set overlay shape region");
 
-    let context_success =
-        unsafe { glx::MakeCurrent(display as *mut glx::types::Display, overlay as u64, glx_ctx) };
-    if context_success == 0 {
-        panic!("unable to make context current");
-    }
-    gl::load_with(|s| unsafe {
-        let c_str = CString::new(s).unwrap();
-        glx::GetProcAddress(c_str.as_ptr() as *const u8) as *const _
-    });
-    unsafe {
-        println!(
-            "GL Version: {}",
-            CStr::from_ptr(gl::GetString(gl::VERSION) as *const i8)
-                .to_str()
-                .expect("unable to parse cstring")
-        );
-    }
-
-    #[rustfmt::skip]
-    let desc = gl_renderer::WindowDrawDesc::new_shader_paths(
-        // square in bottom right
-        &vec![
-            // x,y, u,v
-            0.0,  0.0, 0.0, 1.0,
-            0.0, -1.0, 0.0, 0.0,
-            1.0,  0.0, 1.0, 1.0,
-            1.0, -1.0, 1.0, 0.0,
-        ],
-        &vec![
-            0, 1, 2,
-            2, 1, 3
-        ],
-        // &vec![
-        //     // x,y, u,v
-        //     -1.0, -1.0, 0.0, 0.0,
-        //      1.0, -1.0, 1.0, 0.0,
-        //      1.0,  1.0, 1.0, 1.0,
-        //     -1.0,  1.0, 0.0, 1.0,
-        // ],
-        // &vec![
-        //     0, 1, 2,
-        //     2, 3, 0
-        // ],
-        "./shaders/default_vs.glsl",
-        "./shaders/default_fs.glsl",
-        "./shaders/screen_vs.glsl",
-        "./shaders/screen_fs.glsl",
-        width, height
-    ).expect("could not create window draw description");
-    let renderer = gl_renderer::GLRenderer::new(desc).expect("unable to create renderer");
+    let create_gl = |kind| unsafe {
+        create_gl_renderer(
+            &conn, display, screen_num, overlay, kind, &config, width, height,
+        )
+    };
+    let mut backend: Box<dyn renderer::Backend<XCBConnection>> = match config.backend {
+        renderer::BackendKind::Gl | renderer::BackendKind::Egl => {
+            Box::new(create_gl(config.backend).expect("unable to create gl renderer"))
+        }
+        renderer::BackendKind::XRender => Box::new(
+            xrender_renderer::XRenderRenderer::new(
+                &conn,
+                screen_num,
+                overlay,
+                width,
+                height,
+                config.headless,
+            )
+            .expect("unable to create xrender renderer"),
+        ),
+        renderer::BackendKind::Auto => {
+            let gl = create_gl(renderer::BackendKind::Gl);
+            #[cfg(feature = "egl")]
+            let gl = gl.or_else(|e| {
+                println!("warning: unable to set up glx ({:?}), trying egl", e);
+                create_gl(renderer::BackendKind::Egl)
+            });
+            match gl {
+                Ok(r) => Box::new(r),
+                Err(e) => {
+                    println!(
+                        "warning: unable to set up gl ({:?}), falling back to xrender",
+                        e
+                    );
+                    Box::new(
+                        xrender_renderer::XRenderRenderer::new(
+                            &conn,
+                            screen_num,
+                            overlay,
+                            width,
+                            height,
+                            config.headless,
+                        )
+                        .expect("unable to create xrender renderer"),
+                    )
+                }
+            }
+        }
+    };
+    println!("using renderer: {}", backend.name());
 
     conn.composite_redirect_subwindows(root, x11rb::protocol::composite::Redirect::MANUAL)
         .expect("could not connect to server")
@@ -306,21 +542,52 @@ pub fn main() {
     .expect("could not connect to server")
     .check()
     .expect("unable to register event masks");
+    // so we can resize everything when the resolution changes or a monitor is plugged in
+    conn.randr_select_input(
+        root,
+        NotifyMask::SCREEN_CHANGE | NotifyMask::CRTC_CHANGE | NotifyMask::OUTPUT_CHANGE,
+    )
+    .expect("could not connect to server")
+    .check()
+    .expect("unable to select randr events");
 
-    let mut tracker =
-        win::WinTracker::new(root, overlay, &conn).expect("could not create window tracker");
+    screenshot::install_signal_handler().expect("could not set up screenshots");
+    let mut tracker = win::WinTracker::new(root, overlay, &conn, config)
+        .expect("could not create window tracker");
+    tracker.pacer.set_vsync(backend.vsync());
+    for req in screenshots {
+        tracker.request_screenshot(req);
+    }
+    let mut ipc = ipc::IpcServer::new(tracker.config.ipc_socket.as_deref(), &config_path)
+        .expect("could not create control socket");
+    #[cfg(feature = "dbus")]
+    let mut dbus = match tracker.config.dbus {
+        true => Some(
+            dbus_service::DbusService::new(&tracker, &config_path)
+                .expect("could not start d-bus service"),
+        ),
+        false => None,
+    };
+    #[cfg(not(feature = "dbus"))]
+    if tracker.config.dbus {
+        println!("warning: built without the dbus feature, not starting the d-bus service");
+    }
     loop {
+        if screenshot::take_signal() {
+            let req = screenshot::Request::screen_in_dir(&tracker.config.screenshot_dir);
+            tracker.request_screenshot(req);
+        }
+        if let Err(e) = ipc.poll(&mut tracker, backend.as_mut()) {
+            println!("warning: control socket: {:?}", e);
+        }
+        #[cfg(feature = "dbus")]
+        if let Some(d) = &mut dbus {
+            if let Err(e) = d.poll(&mut tracker, backend.as_mut()) {
+                println!("warning: d-bus service: {:?}", e);
+            }
+        }
         let event = conn.poll_for_event().unwrap();
-        match tracker.process_and_render(
-            &event,
-            display as *mut glx::types::Display,
-            fb_config as *mut c_void,
-            &conn,
-            width,
-            height,
-            overlay,
-            &renderer,
-        ) {
+        match tracker.process_and_render(&event, &conn, backend.as_mut()) {
             Err(e) => {
                 match &e {
                     CompError::Reply(r) => match r {
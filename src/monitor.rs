@@ -0,0 +1,165 @@
+use crate::errors;
+use crate::win::Rect;
+
+use x11rb::protocol::randr::{ConnectionExt, Crtc, ModeFlag, ModeInfo, Output, Rotation};
+use x11rb::protocol::xproto::Window;
+
+/// an enabled crtc, i.e. a region of the root that is shown on a physical screen
+#[derive(Debug, Clone)]
+pub struct Monitor {
+    pub crtc: Crtc,
+    /// the first output driven by the crtc (more than one if mirrored)
+    pub output: Output,
+    pub name: String,
+    /// position in the root window, already accounts for rotation
+    pub rect: Rect,
+    /// in Hz, 0 if the mode didn't give enough info to work it out
+    pub refresh_rate: f32,
+    pub rotation: Rotation,
+    pub primary: bool,
+}
+
+impl Monitor {
+    pub fn overlap(&self, rect: &Rect) -> u32 {
+        self.rect
+            .intersect(rect)
+            .map_or(0, |r| r.width as u32 * r.height as u32)
+    }
+}
+
+/// refresh rate as xrandr works it out
+fn refresh_rate(mode: &ModeInfo) -> f32 {
+    let mut vtotal = mode.vtotal as f32;
+    if mode.mode_flags & u32::from(ModeFlag::DOUBLE_SCAN) != 0 {
+        vtotal *= 2.0;
+    }
+    if mode.mode_flags & u32::from(ModeFlag::INTERLACE) != 0 {
+        vtotal /= 2.0;
+    }
+    match mode.htotal != 0 && vtotal != 0.0 {
+        true => mode.dot_clock as f32 / (mode.htotal as f32 * vtotal),
+        false => 0.0,
+    }
+}
+
+pub fn query_monitors(
+    conn: &impl x11rb::connection::Connection,
+    root: Window,
+) -> Result<Vec<Monitor>, errors::CompError> {
+    let resources = conn.randr_get_screen_resources_current(root)?.reply()?;
+    let primary = conn.randr_get_output_primary(root)?.reply()?.output;
+
+    let mut ret = vec![];
+    for &crtc in resources.crtcs.iter() {
+        let info = conn
+            .randr_get_crtc_info(crtc, resources.config_timestamp)?
+            .reply()?;
+        // disabled crtc
+        if info.mode == 0 || info.outputs.is_empty() {
+            continue;
+        }
+        let output = conn
+            .randr_get_output_info(info.outputs[0], resources.config_timestamp)?
+            .reply()?;
+        ret.push(Monitor {
+            crtc,
+            output: info.outputs[0],
+            name: String::from_utf8_lossy(&output.name).into_owned(),
+            rect: Rect::new(info.x, info.y, info.width, info.height),
+            refresh_rate: resources
+                .modes
+                .iter()
+                .find(|m| m.id == info.mode)
+                .map_or(0.0, refresh_rate),
+            rotation: Rotation::from(info.rotation as u8),
+            primary: info.outputs.contains(&primary),
+        });
+    }
+    Ok(ret)
+}
+
+/// the monitor showing most of `rect`, if any of it is visible
+pub fn monitor_for(monitors: &[Monitor], rect: &Rect) -> Option<usize> {
+    monitors
+        .iter()
+        .enumerate()
+        .map(|(i, m)| (i, m.overlap(rect)))
+        .filter(|&(_, area)| area > 0)
+        .max_by_key(|&(_, area)| area)
+        .map(|(i, _)| i)
+}
+
+/// a monitor that didn't come from the server, for tests
+#[cfg(test)]
+pub(crate) fn test_monitor(rect: Rect, refresh_rate: f32, primary: bool) -> Monitor {
+    Monitor {
+        crtc: 0,
+        output: 0,
+        name: String::new(),
+        rect,
+        refresh_rate,
+        rotation: Rotation::ROTATE0,
+        primary,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mode(dot_clock: u32, htotal: u16, vtotal: u16, mode_flags: u32) -> ModeInfo {
+        ModeInfo {
+            id: 1,
+            width: 1920,
+            height: 1080,
+            dot_clock,
+            hsync_start: 0,
+            hsync_end: 0,
+            htotal,
+            hskew: 0,
+            vsync_start: 0,
+            vsync_end: 0,
+            vtotal,
+            name_len: 0,
+            mode_flags,
+        }
+    }
+
+    #[test]
+    fn overlap() {
+        let m = test_monitor(Rect::new(0, 0, 1920, 1080), 60.0, false);
+        assert_eq!(m.overlap(&Rect::new(1900, 1000, 100, 100)), 20 * 80);
+        assert_eq!(m.overlap(&Rect::new(-50, -50, 10, 10)), 0);
+    }
+
+    #[test]
+    fn monitor_for_most_overlap() {
+        let monitors = [
+            test_monitor(Rect::new(0, 0, 1920, 1080), 60.0, false),
+            test_monitor(Rect::new(1920, 0, 1920, 1080), 60.0, false),
+        ];
+        assert_eq!(
+            monitor_for(&monitors, &Rect::new(1800, 0, 200, 100)),
+            Some(0)
+        );
+        assert_eq!(
+            monitor_for(&monitors, &Rect::new(1880, 0, 200, 100)),
+            Some(1)
+        );
+        assert_eq!(monitor_for(&monitors, &Rect::new(0, 2000, 100, 100)), None);
+        assert_eq!(monitor_for(&[], &Rect::new(0, 0, 100, 100)), None);
+    }
+
+    #[test]
+    fn refresh_rate_from_mode() {
+        assert_eq!(refresh_rate(&mode(148_500_000, 2200, 1125, 0)), 60.0);
+        let interlace = u32::from(ModeFlag::INTERLACE);
+        assert_eq!(refresh_rate(&mode(74_250_000, 2200, 1125, interlace)), 60.0);
+        let double_scan = u32::from(ModeFlag::DOUBLE_SCAN);
+        assert_eq!(
+            refresh_rate(&mode(148_500_000, 2200, 1125, double_scan)),
+            30.0
+        );
+        assert_eq!(refresh_rate(&mode(148_500_000, 0, 1125, 0)), 0.0);
+    }
+}
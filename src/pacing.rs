@@ -0,0 +1,202 @@
+use crate::errors;
+use crate::monitor::Monitor;
+
+use std::time::{Duration, Instant};
+
+/// used when we can't get a refresh rate out of randr
+const FALLBACK_REFRESH_RATE: f32 = 60.0;
+/// how much each new frame counts towards the smoothed frame time
+const FRAME_TIME_SMOOTHING: f32 = 0.1;
+/// with vsync, how far into a refresh period the next frame is started, the swap then waits for the vblank
+/// starting a full period after the last swap would often miss it and halve the frame rate
+const VSYNC_FRAME_START: f32 = 0.5;
+
+/// which monitor's refresh rate we render at
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PacingMode {
+    Fastest,
+    Primary,
+    /// the monitor that got the most damage since the last frame
+    MostDamage,
+}
+
+impl PacingMode {
+    pub fn parse(s: &str) -> Result<PacingMode, errors::CompError> {
+        match s {
+            "fastest" => Ok(PacingMode::Fastest),
+            "primary" => Ok(PacingMode::Primary),
+            "damage" => Ok(PacingMode::MostDamage),
+            _ => Err(format!(
+                "unknown frame pacing '{}', expected fastest, primary or damage",
+                s
+            ))?,
+        }
+    }
+}
+
+impl Default for PacingMode {
+    fn default() -> PacingMode {
+        PacingMode::Fastest
+    }
+}
+
+#[derive(Debug)]
+pub struct FramePacer {
+    refresh_rate: f32,
+    last_frame: Instant,
+    /// smoothed time between presented frames
+    frame_time: Duration,
+    /// presenting waits for vblank
+    vsync: bool,
+    last_report: Instant,
+}
+
+impl FramePacer {
+    pub fn new() -> FramePacer {
+        let now = Instant::now();
+        FramePacer {
+            refresh_rate: FALLBACK_REFRESH_RATE,
+            last_frame: now,
+            frame_time: Duration::from_secs_f32(1.0 / FALLBACK_REFRESH_RATE),
+            vsync: false,
+            last_report: now,
+        }
+    }
+
+    /// `damage` is the damaged area per monitor since the last frame, same order as `monitors`
+    pub fn pick_refresh_rate(&mut self, mode: PacingMode, monitors: &[Monitor], damage: &[u64]) {
+        let fastest = monitors.iter().map(|m| m.refresh_rate).fold(0.0, f32::max);
+        let rate = match mode {
+            PacingMode::Fastest => fastest,
+            PacingMode::Primary => monitors
+                .iter()
+                .find(|m| m.primary)
+                .map_or(fastest, |m| m.refresh_rate),
+            PacingMode::MostDamage => match damage
+                .iter()
+                .enumerate()
+                .filter(|&(_, &d)| d > 0)
+                .max_by_key(|&(_, &d)| d)
+            {
+                Some((i, _)) => monitors[i].refresh_rate,
+                // nothing changed, no point speeding up or slowing down
+                None => self.refresh_rate,
+            },
+        };
+        self.refresh_rate = match rate > 0.0 {
+            true => rate,
+            false => FALLBACK_REFRESH_RATE,
+        };
+    }
+
+    pub fn refresh_rate(&self) -> f32 {
+        self.refresh_rate
+    }
+
+    pub fn frame_time(&self) -> Duration {
+        self.frame_time
+    }
+
+    pub fn set_vsync(&mut self, vsync: bool) {
+        self.vsync = vsync;
+    }
+
+    fn next_frame(&self) -> Instant {
+        let start = match self.vsync {
+            true => VSYNC_FRAME_START,
+            false => 1.0,
+        };
+        self.last_frame + Duration::from_secs_f32(start / self.refresh_rate)
+    }
+
+    pub fn frame_due(&self) -> bool {
+        Instant::now() >= self.next_frame()
+    }
+
+    /// sleep until the next frame should be drawn
+    pub fn wait_for_frame(&self) {
+        let now = Instant::now();
+        let next = self.next_frame();
+        if next > now {
+            std::thread::sleep(next - now);
+        }
+    }
+
+    /// call instead of `frame_presented` when nothing changed, so the next frame isn't due right away
+    pub fn frame_skipped(&mut self) {
+        self.last_frame = Instant::now();
+    }
+
+    /// call right after swapping buffers
+    pub fn frame_presented(&mut self) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_frame).as_secs_f32();
+        self.frame_time = Duration::from_secs_f32(
+            self.frame_time.as_secs_f32() * (1.0 - FRAME_TIME_SMOOTHING)
+                + elapsed * FRAME_TIME_SMOOTHING,
+        );
+        self.last_frame = now;
+
+        if now.duration_since(self.last_report) >= Duration::from_secs(1) {
+            println!(
+                "frame time: {:.2}ms (target {:.2}Hz)",
+                self.frame_time().as_secs_f32() * 1000.0,
+                self.refresh_rate
+            );
+            self.last_report = now;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::monitor::test_monitor;
+    use crate::win::Rect;
+
+    fn monitor(refresh_rate: f32, primary: bool) -> Monitor {
+        test_monitor(Rect::new(0, 0, 1920, 1080), refresh_rate, primary)
+    }
+
+    #[test]
+    fn parse_modes() {
+        assert_eq!(PacingMode::parse("primary").unwrap(), PacingMode::Primary);
+        assert!(PacingMode::parse("slowest").is_err());
+    }
+
+    #[test]
+    fn pick_fastest_and_primary() {
+        let monitors = [monitor(60.0, true), monitor(144.0, false)];
+        let mut pacer = FramePacer::new();
+        pacer.pick_refresh_rate(PacingMode::Fastest, &monitors, &[0, 0]);
+        assert_eq!(pacer.refresh_rate, 144.0);
+        pacer.pick_refresh_rate(PacingMode::Primary, &monitors, &[0, 0]);
+        assert_eq!(pacer.refresh_rate, 60.0);
+        // no primary falls back to the fastest
+        let monitors = [monitor(60.0, false), monitor(75.0, false)];
+        pacer.pick_refresh_rate(PacingMode::Primary, &monitors, &[0, 0]);
+        assert_eq!(pacer.refresh_rate, 75.0);
+    }
+
+    #[test]
+    fn pick_most_damage() {
+        let monitors = [monitor(60.0, true), monitor(144.0, false)];
+        let mut pacer = FramePacer::new();
+        pacer.pick_refresh_rate(PacingMode::MostDamage, &monitors, &[500, 20]);
+        assert_eq!(pacer.refresh_rate, 60.0);
+        pacer.pick_refresh_rate(PacingMode::MostDamage, &monitors, &[0, 20]);
+        assert_eq!(pacer.refresh_rate, 144.0);
+        // no damage keeps the last rate
+        pacer.pick_refresh_rate(PacingMode::MostDamage, &monitors, &[0, 0]);
+        assert_eq!(pacer.refresh_rate, 144.0);
+    }
+
+    #[test]
+    fn pick_falls_back() {
+        let mut pacer = FramePacer::new();
+        pacer.pick_refresh_rate(PacingMode::Fastest, &[], &[]);
+        assert_eq!(pacer.refresh_rate, FALLBACK_REFRESH_RATE);
+        pacer.pick_refresh_rate(PacingMode::Primary, &[monitor(0.0, true)], &[0]);
+        assert_eq!(pacer.refresh_rate, FALLBACK_REFRESH_RATE);
+    }
+}
@@ -0,0 +1,371 @@
+use crate::errors;
+use crate::renderer;
+use crate::screenshot;
+
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::sync::mpsc::{self, SyncSender, TrySendError};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RecordFormat {
+    /// a yuv 4:4:4 stream most video tools can read, e.g. `ffmpeg -i out.y4m`
+    Y4m,
+    /// rgba frames one after the other, with no header
+    Raw,
+    /// `path` is a directory, frames are saved as 000000.png, 000001.png, ...
+    Png,
+}
+
+impl RecordFormat {
+    pub fn parse(s: &str) -> Result<RecordFormat, errors::CompError> {
+        match s {
+            "y4m" => Ok(RecordFormat::Y4m),
+            "raw" => Ok(RecordFormat::Raw),
+            "png" => Ok(RecordFormat::Png),
+            _ => Err(format!(
+                "unknown record format '{}', expected y4m, raw or png",
+                s
+            ))?,
+        }
+    }
+}
+
+impl Default for RecordFormat {
+    fn default() -> RecordFormat {
+        RecordFormat::Y4m
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RecordConfig {
+    /// not recording if `None`
+    pub path: Option<String>,
+    pub format: RecordFormat,
+    /// frames per second to capture, independent of how often we render
+    pub fps: f32,
+}
+
+impl Default for RecordConfig {
+    fn default() -> RecordConfig {
+        RecordConfig {
+            path: None,
+            format: RecordFormat::default(),
+            fps: 30.0,
+        }
+    }
+}
+
+/// frames read back but not written yet, past this the newest are dropped rather than stalling rendering
+const WRITE_QUEUE_LEN: usize = 8;
+
+/// dumps composited frames to disk as they're presented
+/// each frame's time since recording started goes in a `<path>.timestamps` file next to it,
+/// as `<frame> <seconds> <width> <height>` lines
+/// frames are only captured when one is presented, y4m fills the gaps in between by repeating the last frame
+/// as it has a fixed frame rate, for raw and png only the timestamps say when each frame was shown
+/// encoding and writing happen on a thread of their own
+#[derive(Debug)]
+pub struct Recorder {
+    frame_interval: Duration,
+
+    start: Instant,
+    next_frame: Instant,
+    /// when each of the readbacks still in flight was started
+    pending: VecDeque<Duration>,
+
+    frames: Option<SyncSender<(renderer::Frame, Duration)>>,
+    writer: Option<JoinHandle<Result<(), errors::CompError>>>,
+}
+
+impl Recorder {
+    pub fn new(config: &RecordConfig) -> Result<Option<Recorder>, errors::CompError> {
+        let path = match &config.path {
+            Some(p) => p.clone(),
+            None => return Ok(None),
+        };
+        if config.fps <= 0.0 {
+            Err(format!("record fps must be positive, got {}", config.fps))?
+        }
+        let mut writer = FrameWriter::new(config.format, path, config.fps)?;
+        let (frames, queue) = mpsc::sync_channel(WRITE_QUEUE_LEN);
+        let writer = std::thread::spawn(move || {
+            for (frame, time) in queue {
+                writer.write_frame(&frame, time)?;
+            }
+            Ok(())
+        });
+        let now = Instant::now();
+        Ok(Some(Recorder {
+            frame_interval: Duration::from_secs_f32(1.0 / config.fps),
+
+            start: now,
+            next_frame: now,
+            pending: VecDeque::new(),
+
+            frames: Some(frames),
+            writer: Some(writer),
+        }))
+    }
+
+    /// call after every frame, captures it if it's time for the next recorded frame
+    pub fn frame_presented<C: x11rb::connection::Connection>(
+        &mut self,
+        conn: &C,
+        backend: &mut dyn renderer::Backend<C>,
+    ) -> Result<(), errors::CompError> {
+        let now = Instant::now();
+        if now < self.next_frame {
+            return Ok(());
+        }
+        self.next_frame += self.frame_interval;
+        // don't try to catch up after a stall, that would just record the same frame over and over
+        if self.next_frame < now {
+            self.next_frame = now + self.frame_interval;
+        }
+
+        self.pending.push_back(now.duration_since(self.start));
+        if let Some(frame) = backend.read_frame_async(conn)? {
+            let time = self
+                .pending
+                .pop_front()
+                .ok_or("got a recorded frame that was never asked for".to_string())?;
+            let frames = self
+                .frames
+                .as_ref()
+                .ok_or("the recording has stopped".to_string())?;
+            match frames.try_send((frame, time)) {
+                Ok(()) => (),
+                Err(TrySendError::Full(_)) => {
+                    println!("warning: recording can't keep up, dropping a frame")
+                }
+                // the writer only stops early when it fails
+                Err(TrySendError::Disconnected(_)) => {
+                    self.finish()?;
+                    Err("the recording stopped".to_string())?
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// wait for everything queued to be written
+    fn finish(&mut self) -> Result<(), errors::CompError> {
+        self.frames = None;
+        match self.writer.take().map(|w| w.join()) {
+            Some(Ok(res)) => res,
+            Some(Err(_)) => Err("the recording thread panicked".to_string())?,
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for Recorder {
+    fn drop(&mut self) {
+        if let Err(e) = self.finish() {
+            println!("warning: recording failed: {:?}", e);
+        }
+    }
+}
+
+/// the part of the recorder that runs on its own thread
+#[derive(Debug)]
+struct FrameWriter {
+    format: RecordFormat,
+    path: String,
+    fps: f32,
+    frames_written: u64,
+
+    /// the video stream, not used for png sequences
+    out: Option<BufWriter<File>>,
+    timestamps: BufWriter<File>,
+    /// the stream header can only describe one size, so frames of any other size are dropped
+    y4m_size: Option<(u16, u16)>,
+    /// the last y4m frame, converted, for filling gaps
+    last_yuv: Vec<u8>,
+}
+
+impl FrameWriter {
+    fn new(format: RecordFormat, path: String, fps: f32) -> Result<FrameWriter, errors::CompError> {
+        let out = match format {
+            RecordFormat::Png => {
+                std::fs::create_dir_all(&path)?;
+                None
+            }
+            _ => Some(BufWriter::new(File::create(&path)?)),
+        };
+        println!("recording to {} as {:?}", path, format);
+        Ok(FrameWriter {
+            format,
+            timestamps: BufWriter::new(File::create(format!(
+                "{}.timestamps",
+                path.trim_end_matches('/')
+            ))?),
+            path,
+            fps,
+            frames_written: 0,
+
+            out,
+            y4m_size: None,
+            last_yuv: vec![],
+        })
+    }
+
+    fn write_frame(
+        &mut self,
+        frame: &renderer::Frame,
+        time: Duration,
+    ) -> Result<(), errors::CompError> {
+        match self.format {
+            RecordFormat::Png => screenshot::write_png(
+                frame,
+                &format!("{}/{:06}.png", self.path, self.frames_written),
+            )?,
+            RecordFormat::Raw => self.stream()?.write_all(&frame.data)?,
+            RecordFormat::Y4m => {
+                let size = (frame.width, frame.height);
+                if self.y4m_size.is_none() {
+                    let header = y4m_header(frame.width, frame.height, self.fps);
+                    self.stream()?.write_all(header.as_bytes())?;
+                    self.y4m_size = Some(size);
+                }
+                if self.y4m_size != Some(size) {
+                    println!(
+                        "warning: dropping {}x{} frame, the recording is {:?}",
+                        frame.width, frame.height, self.y4m_size
+                    );
+                    return Ok(());
+                }
+                // nothing was presented in between, so the last frame was still on screen
+                let due = (time.as_secs_f64() * self.fps as f64).round() as u64;
+                while self.frames_written < due && !self.last_yuv.is_empty() {
+                    let time =
+                        Duration::from_secs_f64(self.frames_written as f64 / self.fps as f64);
+                    self.write_last_yuv()?;
+                    self.write_timestamp(time, size)?;
+                }
+                self.last_yuv = rgba_to_yuv444(&frame.data);
+                self.write_last_yuv()?;
+            }
+        }
+        self.write_timestamp(time, (frame.width, frame.height))?;
+        // the compositor is usually killed rather than exiting, so nothing is left in the buffers
+        if let Some(out) = &mut self.out {
+            out.flush()?;
+        }
+        self.timestamps.flush()?;
+        Ok(())
+    }
+
+    /// `last_yuv` as the next frame of the y4m stream
+    fn write_last_yuv(&mut self) -> Result<(), errors::CompError> {
+        let out = self
+            .out
+            .as_mut()
+            .ok_or("no stream to write to".to_string())?;
+        out.write_all(b"FRAME\n")?;
+        out.write_all(&self.last_yuv)?;
+        Ok(())
+    }
+
+    fn write_timestamp(
+        &mut self,
+        time: Duration,
+        (width, height): (u16, u16),
+    ) -> Result<(), errors::CompError> {
+        writeln!(
+            self.timestamps,
+            "{} {:.6} {} {}",
+            self.frames_written,
+            time.as_secs_f64(),
+            width,
+            height
+        )?;
+        self.frames_written += 1;
+        Ok(())
+    }
+
+    fn stream(&mut self) -> Result<&mut BufWriter<File>, errors::CompError> {
+        Ok(self
+            .out
+            .as_mut()
+            .ok_or("no stream to write to".to_string())?)
+    }
+}
+
+/// the frame rate has to be a ratio of integers
+fn y4m_header(width: u16, height: u16, fps: f32) -> String {
+    format!(
+        "YUV4MPEG2 W{} H{} F{}:1000 Ip A1:1 C444\n",
+        width,
+        height,
+        (fps * 1000.0).round() as u32
+    )
+}
+
+/// bt.601 (what y4m players assume), planar y then u then v
+fn rgba_to_yuv444(rgba: &[u8]) -> Vec<u8> {
+    let pixels = rgba.len() / 4;
+    let mut ret = vec![0; pixels * 3];
+    let (y, uv) = ret.split_at_mut(pixels);
+    let (u, v) = uv.split_at_mut(pixels);
+    for (i, p) in rgba.chunks_exact(4).enumerate() {
+        let (r, g, b) = (p[0] as f32, p[1] as f32, p[2] as f32);
+        y[i] = (16.0 + 0.257 * r + 0.504 * g + 0.098 * b).round() as u8;
+        u[i] = (128.0 - 0.148 * r - 0.291 * g + 0.439 * b).round() as u8;
+        v[i] = (128.0 + 0.439 * r - 0.368 * g - 0.071 * b).round() as u8;
+    }
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn yuv_planes() {
+        // white then black
+        let yuv = rgba_to_yuv444(&[255, 255, 255, 255, 0, 0, 0, 255]);
+        assert_eq!(yuv, vec![235, 16, 128, 128, 128, 128]);
+        let red = rgba_to_yuv444(&[255, 0, 0, 255]);
+        assert_eq!(red, vec![82, 90, 240]);
+    }
+
+    #[test]
+    fn y4m_header_fps() {
+        assert_eq!(
+            y4m_header(640, 480, 30.0),
+            "YUV4MPEG2 W640 H480 F30000:1000 Ip A1:1 C444\n"
+        );
+        assert!(y4m_header(640, 480, 29.97).contains(" F29970:1000 "));
+    }
+
+    #[test]
+    fn y4m_fills_gaps() {
+        let path = std::env::temp_dir().join(format!("xcomprs-test-{}.y4m", std::process::id()));
+        let path = path.to_str().unwrap().to_string();
+        let frame = renderer::Frame {
+            width: 1,
+            height: 1,
+            data: vec![0, 0, 0, 255],
+        };
+        let mut writer = FrameWriter::new(RecordFormat::Y4m, path.clone(), 30.0).unwrap();
+        writer.write_frame(&frame, Duration::from_secs(0)).unwrap();
+        // 0.1s later at 30fps, so two frames are repeated before this one
+        writer
+            .write_frame(&frame, Duration::from_millis(100))
+            .unwrap();
+        assert_eq!(writer.frames_written, 4);
+        drop(writer);
+
+        let data = std::fs::read(&path).unwrap();
+        let header = y4m_header(1, 1, 30.0);
+        assert_eq!(data.len(), header.len() + 4 * (b"FRAME\n".len() + 3));
+        let timestamps = std::fs::read_to_string(format!("{}.timestamps", path)).unwrap();
+        assert_eq!(timestamps.lines().count(), 4);
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(format!("{}.timestamps", path)).unwrap();
+    }
+}
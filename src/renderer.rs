@@ -0,0 +1,95 @@
+use crate::errors;
+use crate::win;
+
+use std::fmt::Debug;
+use std::io::Write;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BackendKind {
+    /// gl through glx, then egl if built with it, falling back to xrender if gl can't be set up
+    Auto,
+    Gl,
+    /// gl through egl, importing window pixmaps as EGLImages, needs the egl feature
+    Egl,
+    XRender,
+}
+
+impl BackendKind {
+    pub fn parse(s: &str) -> Result<BackendKind, errors::CompError> {
+        match s {
+            "auto" => Ok(BackendKind::Auto),
+            "gl" => Ok(BackendKind::Gl),
+            "egl" => Ok(BackendKind::Egl),
+            "xrender" => Ok(BackendKind::XRender),
+            _ => Err(format!(
+                "unknown backend '{}', expected auto, gl, egl or xrender",
+                s
+            ))?,
+        }
+    }
+}
+
+impl Default for BackendKind {
+    fn default() -> BackendKind {
+        BackendKind::Auto
+    }
+}
+
+/// a readback of (part of) the composited output
+#[derive(Debug, Clone)]
+pub struct Frame {
+    pub width: u16,
+    pub height: u16,
+    /// rgba, 4 bytes per pixel, rows go from the top of the screen to the bottom
+    pub data: Vec<u8>,
+}
+
+impl Frame {
+    /// write as a netpbm PAM, which is just a short text header followed by the rgba data
+    /// written to a temporary file first, so anything watching `path` never sees half a frame
+    pub fn write_pam(&self, path: &str) -> Result<(), errors::CompError> {
+        let tmp = format!("{}.tmp", path);
+        let mut file = std::io::BufWriter::new(std::fs::File::create(&tmp)?);
+        write!(
+            file,
+            "P7\nWIDTH {}\nHEIGHT {}\nDEPTH 4\nMAXVAL 255\nTUPLTYPE RGB_ALPHA\nENDHDR\n",
+            self.width, self.height
+        )?;
+        file.write_all(&self.data)?;
+        file.flush()?;
+        std::fs::rename(&tmp, path)?;
+        Ok(())
+    }
+}
+
+/// something that can draw the tracked windows to the screen
+/// per window resources (textures, pictures, ...) are owned by the backend and looked up by window handle,
+/// so window tracking doesn't need to know what they are
+pub trait Backend<C: x11rb::connection::Connection>: Debug {
+    fn name(&self) -> &'static str;
+    /// make the window's (freshly named) pixmap drawable, replacing whatever was bound for it before
+    fn bind_pixmap(&mut self, win: &win::Win, conn: &C) -> Result<(), errors::CompError>;
+    /// free whatever is held for the window, a noop if nothing is bound
+    fn release_pixmap(&mut self, win: &win::Win, conn: &C) -> Result<(), errors::CompError>;
+    /// resize everything that is the size of the screen
+    fn resize(&mut self, conn: &C, width: u16, height: u16) -> Result<(), errors::CompError>;
+    fn render(&mut self, wins: &win::WinTracker, conn: &C) -> Result<(), errors::CompError>;
+    /// what the last frame looked like inside `rect` (in root coordinates), clipped to the screen
+    fn read_pixels(&mut self, conn: &C, rect: &win::Rect) -> Result<Frame, errors::CompError>;
+    /// start reading back the whole of the last frame without waiting for it,
+    /// returns an earlier frame once it's ready, in the order they were started
+    fn read_frame_async(&mut self, conn: &C) -> Result<Option<Frame>, errors::CompError>;
+    /// how long each pass of the latest frame the gpu has finished took, in ms, by pass name
+    /// `None` if no new frame has finished or the backend can't tell
+    fn gpu_times(&mut self) -> Option<Vec<(&'static str, f32)>> {
+        None
+    }
+    /// presenting waits for vblank, so frames can be started before the refresh period is over
+    fn vsync(&self) -> bool {
+        false
+    }
+    /// recompile shaders from disk, for backends that have any
+    fn reload_shaders(&mut self) -> Result<(), errors::CompError> {
+        Ok(())
+    }
+}
@@ -0,0 +1,125 @@
+use crate::errors;
+use crate::renderer;
+
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use x11rb::protocol::xproto::Window;
+
+/// what part of the screen to capture
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Target {
+    Screen,
+    /// index into the tracker's monitors
+    Monitor(usize),
+    /// either a frame or a client window, only the window's own region is captured
+    Window(Window),
+}
+
+impl Target {
+    /// `screen`, `monitor:<index>` or `window:<id>` (id in decimal or 0x hex, as xwininfo prints it)
+    pub fn parse(s: &str) -> Result<Target, errors::CompError> {
+        let bad = || format!("bad screenshot target '{}'", s);
+        match s.split_once(':') {
+            None if s == "screen" => Ok(Target::Screen),
+            Some(("monitor", i)) => Ok(Target::Monitor(i.parse().map_err(|_| bad())?)),
+            Some(("window", id)) => {
+                let id = match id.strip_prefix("0x") {
+                    Some(hex) => Window::from_str_radix(hex, 16),
+                    None => id.parse(),
+                };
+                Ok(Target::Window(id.map_err(|_| bad())?))
+            }
+            _ => Err(bad())?,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Request {
+    pub target: Target,
+    pub path: String,
+}
+
+impl Request {
+    /// the whole screen, saved in `dir` (the working directory if empty) with the time in the file name
+    pub fn screen_in_dir(dir: &str) -> Request {
+        let secs = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        Request {
+            target: Target::Screen,
+            path: Path::new(dir)
+                .join(format!("xcomprs-{}.png", secs))
+                .to_string_lossy()
+                .into_owned(),
+        }
+    }
+}
+
+pub fn write_png(frame: &renderer::Frame, path: &str) -> Result<(), errors::CompError> {
+    let file = std::io::BufWriter::new(std::fs::File::create(path)?);
+    let mut encoder = png::Encoder::new(file, frame.width as u32, frame.height as u32);
+    encoder.set_color(png::ColorType::RGBA);
+    encoder.set_depth(png::BitDepth::Eight);
+    // frames are already top to bottom, which is what png wants
+    encoder.write_header()?.write_image_data(&frame.data)?;
+    Ok(())
+}
+
+static SIGNALLED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn on_signal(_: libc::c_int) {
+    SIGNALLED.store(true, Ordering::SeqCst);
+}
+
+/// SIGUSR1 takes a screenshot of the whole screen
+pub fn install_signal_handler() -> Result<(), errors::CompError> {
+    let handler = on_signal as extern "C" fn(libc::c_int) as libc::sighandler_t;
+    if unsafe { libc::signal(libc::SIGUSR1, handler) } == libc::SIG_ERR {
+        Err("unable to install SIGUSR1 handler".to_string())?
+    }
+    Ok(())
+}
+
+/// whether SIGUSR1 was received since the last call
+pub fn take_signal() -> bool {
+    SIGNALLED.swap(false, Ordering::SeqCst)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_targets() {
+        assert_eq!(Target::parse("screen").unwrap(), Target::Screen);
+        assert_eq!(Target::parse("monitor:1").unwrap(), Target::Monitor(1));
+        assert_eq!(
+            Target::parse("window:0x1a00003").unwrap(),
+            Target::Window(0x1a00003)
+        );
+        assert_eq!(
+            Target::parse("window:4194307").unwrap(),
+            Target::Window(4194307)
+        );
+    }
+
+    #[test]
+    fn parse_bad_targets() {
+        for s in [
+            "",
+            "screen:0",
+            "monitor",
+            "monitor:-1",
+            "window:0xzz",
+            "window:",
+            "root",
+        ]
+        .iter()
+        {
+            assert!(Target::parse(s).is_err(), "'{}' parsed", s);
+        }
+    }
+}
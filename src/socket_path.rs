@@ -0,0 +1,18 @@
+// shared with xcomprs-msg, so this can't depend on anything else in the crate
+
+use std::path::PathBuf;
+
+/// one socket per display, in $XDG_RUNTIME_DIR (or /tmp if that isn't set)
+pub fn default_socket_path() -> PathBuf {
+    let dir = std::env::var("XDG_RUNTIME_DIR").unwrap_or("/tmp".to_string());
+    // e.g. ":0" or "localhost:10.0", which can't all go in a file name as is
+    let display: String = std::env::var("DISPLAY")
+        .unwrap_or_default()
+        .chars()
+        .map(|c| match c {
+            '/' | ':' => '_',
+            c => c,
+        })
+        .collect();
+    PathBuf::from(dir).join(format!("xcomprs{}.sock", display))
+}
@@ -0,0 +1,124 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// how many frames of history are kept, also how many bars the hud graph has
+pub const HISTORY_LEN: usize = 240;
+
+/// the last `HISTORY_LEN` samples of something measured every frame
+#[derive(Debug, Clone)]
+pub struct History {
+    samples: VecDeque<f32>,
+}
+
+impl Default for History {
+    fn default() -> History {
+        History {
+            samples: VecDeque::with_capacity(HISTORY_LEN),
+        }
+    }
+}
+
+impl History {
+    pub fn push(&mut self, sample: f32) {
+        if self.samples.len() == HISTORY_LEN {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(sample);
+    }
+
+    /// oldest first
+    pub fn iter(&self) -> impl Iterator<Item = f32> + '_ {
+        self.samples.iter().copied()
+    }
+
+    pub fn last(&self) -> f32 {
+        self.samples.back().copied().unwrap_or(0.0)
+    }
+
+    pub fn mean(&self) -> f32 {
+        match self.samples.len() {
+            0 => 0.0,
+            n => self.iter().sum::<f32>() / n as f32,
+        }
+    }
+
+    pub fn max(&self) -> f32 {
+        self.iter().fold(0.0, f32::max)
+    }
+
+    /// `p` between 0 and 1, e.g. 0.99 for the 99th percentile
+    pub fn percentile(&self, p: f32) -> f32 {
+        let mut sorted: Vec<f32> = self.iter().collect();
+        if sorted.is_empty() {
+            return 0.0;
+        }
+        sorted.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+        sorted[((sorted.len() - 1) as f32 * p.max(0.0).min(1.0)).round() as usize]
+    }
+
+    /// counts of samples in `[i * width, (i + 1) * width)`, the last bucket also gets everything above
+    pub fn histogram(&self, width: f32, buckets: usize) -> Vec<u32> {
+        let mut ret = vec![0; buckets];
+        if buckets == 0 {
+            return ret;
+        }
+        for s in self.iter() {
+            let i = ((s / width).max(0.0) as usize).min(buckets - 1);
+            ret[i] += 1;
+        }
+        ret
+    }
+}
+
+/// rolling timings for the last few seconds of frames, all times in milliseconds
+#[derive(Debug, Default)]
+pub struct FrameStats {
+    /// time spent in process_and_render for each frame, including the events before it,
+    /// but not the time spent waiting for the frame to be due
+    pub cpu: History,
+    /// gpu time of the whole frame, from timer queries, a few frames behind
+    pub gpu: History,
+    /// gpu time per render pass, by name
+    pub gpu_passes: Vec<(&'static str, History)>,
+    /// time between presenting one frame and the next
+    pub present_interval: History,
+    /// damaged pixels each frame
+    pub damage: History,
+
+    last_present: Option<Instant>,
+}
+
+impl FrameStats {
+    pub fn frame_presented(&mut self, cpu: Duration, damage: u64) {
+        let now = Instant::now();
+        self.cpu.push(cpu.as_secs_f32() * 1000.0);
+        self.damage.push(damage as f32);
+        if let Some(last) = self.last_present {
+            self.present_interval
+                .push(now.duration_since(last).as_secs_f32() * 1000.0);
+        }
+        self.last_present = Some(now);
+    }
+
+    /// `passes` are the gpu times of one frame, as given by the backend
+    pub fn gpu_frame(&mut self, passes: &[(&'static str, f32)]) {
+        self.gpu.push(passes.iter().map(|(_, t)| t).sum());
+        for &(name, time) in passes {
+            match self.gpu_passes.iter_mut().find(|(n, _)| *n == name) {
+                Some((_, h)) => h.push(time),
+                None => {
+                    let mut h = History::default();
+                    h.push(time);
+                    self.gpu_passes.push((name, h));
+                }
+            }
+        }
+    }
+
+    pub fn fps(&self) -> f32 {
+        match self.present_interval.mean() {
+            t if t > 0.0 => 1000.0 / t,
+            _ => 0.0,
+        }
+    }
+}
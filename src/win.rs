@@ -1,34 +1,47 @@
+use crate::config;
+use crate::damage;
 use crate::errors;
 
+use crate::ewm;
 use crate::ewm::RootWindowHintCodes;
-use crate::gl;
-use crate::gl_renderer;
-use crate::glx;
+use crate::monitor;
+use crate::pacing;
+use crate::recorder;
+use crate::renderer;
+use crate::screenshot;
+use crate::stats;
 
+use std::collections::VecDeque;
 use std::convert::TryFrom;
 
-use std::ffi::c_void;
 use std::fmt::Debug;
+use std::time::{Duration, Instant};
 
 use byteorder::ByteOrder;
 use x11rb::protocol::composite::ConnectionExt as composite_ConnectionExt;
 use x11rb::protocol::damage::ConnectionExt as damage_ConnectionExt;
 use x11rb::protocol::damage::Damage;
 use x11rb::protocol::damage::ReportLevel;
+use x11rb::protocol::randr;
 
+use x11rb::protocol::shape;
+use x11rb::protocol::shape::ConnectionExt as shape_ConnectionExt;
 use x11rb::protocol::xfixes::{ConnectionExt, Region};
 
 use x11rb::protocol::xproto::AtomEnum;
 use x11rb::protocol::xproto::WindowClass;
 use x11rb::protocol::xproto::{
-    ChangeWindowAttributesAux, ConfigureNotifyEvent, ConnectionExt as xproto_ConnectionExt,
-    CreateNotifyEvent, DestroyNotifyEvent, EventMask, MapNotifyEvent, MapState, UnmapNotifyEvent,
-    Window,
+    ChangeWindowAttributesAux, ConfigureNotifyEvent, ConfigureWindowAux,
+    ConnectionExt as xproto_ConnectionExt, CreateNotifyEvent, DestroyNotifyEvent, EventMask,
+    MapNotifyEvent, MapState, UnmapNotifyEvent, Window,
 };
 use x11rb::protocol::Event;
 use x11rb::protocol::Event::*;
 
-#[derive(Debug)]
+/// how long damage stays visible with debug-damage on
+const DAMAGE_FADE_TIME: Duration = Duration::from_millis(500);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct Rect {
     pub x: i16,
     pub y: i16,
@@ -45,6 +58,52 @@ impl Rect {
             height,
         }
     }
+
+    /// the part of `self` inside `other`, if they overlap at all
+    pub fn intersect(&self, other: &Rect) -> Option<Rect> {
+        let x1 = std::cmp::max(self.x as i32, other.x as i32);
+        let y1 = std::cmp::max(self.y as i32, other.y as i32);
+        let x2 = std::cmp::min(
+            self.x as i32 + self.width as i32,
+            other.x as i32 + other.width as i32,
+        );
+        let y2 = std::cmp::min(
+            self.y as i32 + self.height as i32,
+            other.y as i32 + other.height as i32,
+        );
+        match x2 > x1 && y2 > y1 {
+            true => Some(Rect::new(
+                x1 as i16,
+                y1 as i16,
+                (x2 - x1) as u16,
+                (y2 - y1) as u16,
+            )),
+            false => None,
+        }
+    }
+
+    /// `self` with `margin` added on every side, cut down to `clip` so it still fits in a Rect
+    pub fn grow(&self, margin: i32, clip: &Rect) -> Option<Rect> {
+        let x1 = std::cmp::max(self.x as i32 - margin, clip.x as i32);
+        let y1 = std::cmp::max(self.y as i32 - margin, clip.y as i32);
+        let x2 = std::cmp::min(
+            self.x as i32 + self.width as i32 + margin,
+            clip.x as i32 + clip.width as i32,
+        );
+        let y2 = std::cmp::min(
+            self.y as i32 + self.height as i32 + margin,
+            clip.y as i32 + clip.height as i32,
+        );
+        match x2 > x1 && y2 > y1 {
+            true => Some(Rect::new(
+                x1 as i16,
+                y1 as i16,
+                (x2 - x1) as u16,
+                (y2 - y1) as u16,
+            )),
+            false => None,
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -54,18 +113,47 @@ pub struct Win {
     handle: Window,
     pub damage: Damage,
     pub track_damage: bool,
+    /// what `damage` held when it was last emptied, in window coordinates
+    damage_region: Region,
+    /// a damage notify came in since the damage was last collected
+    damaged: bool,
+    /// damage was collected for the frame being drawn, so the contents have changed since the last one
+    contents_changed: bool,
+    /// what that damage was, relative to the window's pixmap (which includes the border)
+    damaged_rects: Vec<Rect>,
 
     border_width: u16,
-    override_redirect: bool,
+    pub override_redirect: bool,
     mapped: bool,
+    /// 32 bit visual, i.e. the window has an alpha channel
+    pub argb: bool,
+    /// the window with WM_STATE, which is a child of `handle` if the wm reparents
+    pub client: Option<Window>,
+    pub win_type: ewm::WinType,
+    /// _NET_WM_STATE_FULLSCREEN was set on the client
+    net_fullscreen: bool,
+    /// either the client asked to be fullscreen, or the window covers the whole screen
+    pub fullscreen: bool,
+    /// the bounding region has been changed with the shape extension
+    pub shaped: bool,
+    /// _NET_WM_OPAQUE_REGION of the client, relative to the inside of the window's border,
+    /// for windows with an alpha channel
+    opaque_region: Vec<Rect>,
+    /// index into the tracker's monitors of the one showing most of the window
+    pub monitor: Option<usize>,
+    /// settings from the config rules that matched this window
+    pub settings: config::WinSettings,
+    /// how much the window is currently darkened, 0 is not at all
+    pub dim: f32,
+    /// set over the control socket, 1 is opaque
+    pub opacity: f32,
+    /// fragment shader to draw the window with instead of the default, set over the control socket
+    pub shader: Option<String>,
 
     // free pixmap each time it changes (i think)
     pub pixmap: x11rb::protocol::xproto::Pixmap,
-
-    // TODO: decouple this more
-    pub glx_pixmap: glx::types::GLXPixmap,
-    /// the gl texture of the window backing pixmap
-    pub texture: gl::types::GLuint,
+    /// the window's visual, for picking a picture format
+    pub visual: x11rb::protocol::xproto::Visualid,
 }
 
 impl Win {
@@ -78,31 +166,29 @@ impl Win {
         border_width: u16,
         override_redirect: bool,
         class: WindowClass,
+        visual: x11rb::protocol::xproto::Visualid,
+        depth: u8,
         mapped: bool,
         conn: &impl x11rb::connection::Connection,
         track_damage: bool,
     ) -> Result<Win, errors::CompError> {
-        let mut ret = Win {
-            handle: handle,
-            damage: 0,
-            track_damage,
-
-            rect: Rect::new(x, y, width, height),
-            border_width: border_width,
-            override_redirect: override_redirect,
-            mapped: mapped,
-
-            pixmap: 0,
-            glx_pixmap: 0,
-            texture: 0,
-        };
-
+        let mut ret = Win::without_damage(
+            handle,
+            Rect::new(x, y, width, height),
+            border_width,
+            override_redirect,
+            visual,
+            depth,
+            mapped,
+        );
+        ret.track_damage = track_damage;
         if class != WindowClass::INPUT_ONLY && track_damage {
             ret.damage = conn.generate_id()?;
-            // conn.damage_create(self.damage, self.handle, ReportLevel::RAW_RECTANGLES)?
-            // conn.damage_create(ret.damage, ret.handle, ReportLevel::DELTA_RECTANGLES)?
-            conn.damage_create(ret.damage, ret.handle, ReportLevel::NON_EMPTY)?
+            // a notify for each rectangle that grows the damage, which is emptied every frame
+            conn.damage_create(ret.damage, ret.handle, ReportLevel::DELTA_RECTANGLES)?
                 .check()?;
+            ret.damage_region = conn.generate_id()?;
+            conn.xfixes_create_region(ret.damage_region, &[])?.check()?;
 
             conn.change_window_attributes(
                 handle,
@@ -113,6 +199,46 @@ impl Win {
 
         Ok(ret)
     }
+    /// just what the window looks like, nothing is asked of the server
+    pub(crate) fn without_damage(
+        handle: Window,
+        rect: Rect,
+        border_width: u16,
+        override_redirect: bool,
+        visual: x11rb::protocol::xproto::Visualid,
+        depth: u8,
+        mapped: bool,
+    ) -> Win {
+        Win {
+            handle: handle,
+            damage: 0,
+            track_damage: false,
+            damage_region: 0,
+            damaged: false,
+            contents_changed: false,
+            damaged_rects: vec![],
+
+            rect,
+            border_width: border_width,
+            override_redirect: override_redirect,
+            mapped: mapped,
+            argb: depth == 32,
+            client: None,
+            win_type: ewm::WinType::Normal,
+            net_fullscreen: false,
+            fullscreen: false,
+            shaped: false,
+            opaque_region: vec![],
+            monitor: None,
+            settings: config::WinSettings::default(),
+            dim: 0.0,
+            opacity: 1.0,
+            shader: None,
+
+            pixmap: 0,
+            visual,
+        }
+    }
     pub fn new_handle(
         handle: Window,
         conn: &impl x11rb::connection::Connection,
@@ -134,6 +260,8 @@ impl Win {
             geom.border_width,
             attrs.override_redirect,
             attrs.class,
+            attrs.visual,
+            geom.depth,
             mapped,
             conn,
             track_damage,
@@ -145,12 +273,19 @@ impl Win {
         track_damage: bool,
     ) -> Result<Win, errors::CompError> {
         let mut class = WindowClass::INPUT_ONLY;
+        let mut visual = 0;
         let attrs = conn.get_window_attributes(evt.window)?.reply();
         if attrs.is_ok() {
-            class = attrs.unwrap().class;
+            let attrs = attrs.unwrap();
+            class = attrs.class;
+            visual = attrs.visual;
         } else {
             println!("warning: window created but could not query its attributes (perhaps it was destroyed immediately?)");
         }
+        let depth = match conn.get_geometry(evt.window)?.reply() {
+            Ok(geom) => geom.depth,
+            Err(_) => 0,
+        };
 
         Win::new_raw(
             evt.window,
@@ -161,25 +296,219 @@ impl Win {
             evt.border_width,
             evt.override_redirect,
             class,
+            visual,
+            depth,
             false,
             conn,
             track_damage,
         )
     }
 
-    pub fn map(
+    pub fn map<C: x11rb::connection::Connection>(
         &mut self,
         evt: &MapNotifyEvent,
-        display: *mut glx::types::Display,
-        config: *const c_void,
-        conn: &impl x11rb::connection::Connection,
-        renderer: &gl_renderer::GLRenderer,
+        conn: &C,
+        backend: &mut dyn renderer::Backend<C>,
     ) -> Result<(), errors::CompError> {
         self.mapped = true;
         self.override_redirect = evt.override_redirect;
-        self.reacquire_pixmap(evt.window, display, config, conn, renderer)?;
+        self.reacquire_pixmap(evt.window, conn, backend)?;
+        Ok(())
+    }
+    /// query the properties the config rules match on, should be called once the window is mapped
+    /// and again when they change
+    /// a window that's destroyed in the meantime gets the defaults, its DestroyNotify is on the way
+    pub fn update_props(
+        &mut self,
+        conn: &impl x11rb::connection::Connection,
+        atoms: &ewm::Atoms,
+        monitors: &[monitor::Monitor],
+        root: &Rect,
+    ) -> Result<(), errors::CompError> {
+        match self.query_props(conn, atoms) {
+            Err(e) if e.is_x11_error() => {
+                self.client = None;
+                self.win_type = ewm::WinType::Normal;
+                self.net_fullscreen = false;
+                self.opaque_region = vec![];
+                self.shaped = false;
+            }
+            res => res?,
+        }
+        self.update_monitor(monitors, root);
+        Ok(())
+    }
+    fn query_props(
+        &mut self,
+        conn: &impl x11rb::connection::Connection,
+        atoms: &ewm::Atoms,
+    ) -> Result<(), errors::CompError> {
+        self.client = ewm::find_client(conn, atoms, self.handle)?;
+        if let Some(client) = self.client {
+            // so the properties can be queried again when they change, any error comes in as an event
+            let mut events = u32::from(EventMask::PROPERTY_CHANGE);
+            if client == self.handle && self.damage != 0 {
+                // the mask replaces the one set when the damage was created
+                events |= u32::from(EventMask::EXPOSURE);
+            }
+            conn.change_window_attributes(
+                client,
+                &ChangeWindowAttributesAux::new().event_mask(events),
+            )?;
+        }
+        conn.shape_select_input(self.handle, true)?;
+        let (win_type, state) = match self.client {
+            Some(client) => (
+                ewm::get_atom_list(conn, client, atoms._NET_WM_WINDOW_TYPE)?,
+                ewm::get_atom_list(conn, client, atoms._NET_WM_STATE)?,
+            ),
+            None => (vec![], vec![]),
+        };
+        self.win_type = ewm::WinType::from_atoms(atoms, &win_type);
+        self.net_fullscreen = state.contains(&atoms._NET_WM_STATE_FULLSCREEN);
+        self.opaque_region = match self.client {
+            Some(client) if self.argb => {
+                let region = ewm::get_cardinal_list(conn, client, atoms._NET_WM_OPAQUE_REGION)?;
+                // the client can be inside a frame
+                let offset = conn
+                    .translate_coordinates(client, self.handle, 0, 0)?
+                    .reply()?;
+                region
+                    .chunks_exact(4)
+                    .map(|r| {
+                        Rect::new(
+                            offset.dst_x + r[0] as i16,
+                            offset.dst_y + r[1] as i16,
+                            r[2] as u16,
+                            r[3] as u16,
+                        )
+                    })
+                    .collect()
+            }
+            _ => vec![],
+        };
+        self.shaped = conn
+            .shape_query_extents(self.handle)?
+            .reply()?
+            .bounding_shaped;
         Ok(())
     }
+    /// work out which monitor we're on, and whether we cover it (or the whole screen)
+    pub fn update_monitor(&mut self, monitors: &[monitor::Monitor], root: &Rect) {
+        self.monitor = monitor::monitor_for(monitors, &self.rect);
+        let covers = |r: &Rect| {
+            self.rect.x <= r.x
+                && self.rect.y <= r.y
+                && self.rect.x as i32 + self.rect.width as i32 >= r.x as i32 + r.width as i32
+                && self.rect.y as i32 + self.rect.height as i32 >= r.y as i32 + r.height as i32
+        };
+        self.fullscreen = self.net_fullscreen
+            || covers(root)
+            || self.monitor.map_or(false, |m| covers(&monitors[m].rect));
+    }
+
+    pub fn handle(&self) -> Window {
+        self.handle
+    }
+
+    pub fn mapped(&self) -> bool {
+        self.mapped
+    }
+
+    /// the window was drawn to since the last frame
+    pub fn contents_changed(&self) -> bool {
+        self.contents_changed
+    }
+
+    /// where the window was drawn to since the last frame, relative to its pixmap
+    pub fn damaged_rects(&self) -> &[Rect] {
+        &self.damaged_rects
+    }
+
+    /// the area the window covers on screen, including its border
+    pub fn screen_rect(&self) -> Rect {
+        Rect::new(
+            self.rect.x,
+            self.rect.y,
+            self.rect.width + self.border_width * 2,
+            self.rect.height + self.border_width * 2,
+        )
+    }
+
+    /// the parts of the window nothing behind it shows through, in root coordinates
+    /// `corner_radius` is cut out of the corners when they're rounded
+    pub fn opaque_rects(&self, corner_radius: f32) -> Vec<Rect> {
+        // the bounding shape isn't queried, so shaped windows could be any shape,
+        // and a custom shader can make any part of the window translucent
+        if !self.mapped || self.opacity < 1.0 || self.shaped || self.shader.is_some() {
+            return vec![];
+        }
+        let border = self.border_width as i16;
+        let outer = self.screen_rect();
+        let mut ret: Vec<Rect> = match self.argb {
+            false => vec![outer],
+            true => self
+                .opaque_region
+                .iter()
+                .filter_map(|r| {
+                    Rect::new(
+                        self.rect.x + border + r.x,
+                        self.rect.y + border + r.y,
+                        r.width,
+                        r.height,
+                    )
+                    .intersect(&outer)
+                })
+                .collect(),
+        };
+        let radius = match self.settings.rounded {
+            true => corner_radius.max(0.0).ceil() as u16,
+            false => 0,
+        };
+        if radius > 0 {
+            // a cross through the middle, which the rounded corners don't reach into
+            let r = &outer;
+            let inner = [
+                Rect::new(
+                    r.x + radius as i16,
+                    r.y,
+                    r.width.saturating_sub(radius * 2),
+                    r.height,
+                ),
+                Rect::new(
+                    r.x,
+                    r.y + radius as i16,
+                    r.width,
+                    r.height.saturating_sub(radius * 2),
+                ),
+            ];
+            ret = ret
+                .iter()
+                .flat_map(|o| inner.iter().filter_map(move |i| o.intersect(i)))
+                .collect();
+        }
+        ret
+    }
+
+    /// `r` from the window's damage, which is relative to the inside of the border, in root coordinates
+    fn damage_to_screen(&self, r: &x11rb::protocol::xproto::Rectangle) -> Rect {
+        let border = self.border_width as i16;
+        Rect::new(
+            self.rect.x + border + r.x,
+            self.rect.y + border + r.y,
+            r.width,
+            r.height,
+        )
+    }
+
+    /// move `dim` towards `target` by at most `max_step`
+    fn step_dim(&mut self, target: f32, max_step: f32) {
+        self.dim = match self.dim < target {
+            true => f32::min(self.dim + max_step, target),
+            false => f32::max(self.dim - max_step, target),
+        };
+    }
+
     pub fn unmap(
         &mut self,
         _evt: &UnmapNotifyEvent,
@@ -189,28 +518,26 @@ impl Win {
         Ok(())
     }
 
-    pub fn destroy(
+    pub fn destroy<C: x11rb::connection::Connection>(
         &mut self,
         _evt: &DestroyNotifyEvent,
-        display: *mut glx::types::Display,
-        conn: &impl x11rb::connection::Connection,
-        renderer: &gl_renderer::GLRenderer,
+        conn: &C,
+        backend: &mut dyn renderer::Backend<C>,
     ) -> Result<(), errors::CompError> {
         if self.damage != 0 {
             // apparently when destroying damage you can get a BadDamage. idk why. both picom and xcompmgr ignore the error :/
             conn.damage_destroy(self.damage)?.ignore_error();
+            conn.xfixes_destroy_region(self.damage_region)?;
         }
-        self.release_pixmap(display, renderer)?;
+        self.release_pixmap(conn, backend)?;
         Ok(())
     }
 
-    pub fn reacquire_pixmap(
+    pub fn reacquire_pixmap<C: x11rb::connection::Connection>(
         &mut self,
         window: Window,
-        display: *mut glx::types::Display,
-        config: *const c_void,
-        conn: &impl x11rb::connection::Connection,
-        renderer: &gl_renderer::GLRenderer,
+        conn: &C,
+        backend: &mut dyn renderer::Backend<C>,
     ) -> Result<(), errors::CompError> {
         // if we're not mapped, no pixmap to reacquire
         if !self.mapped {
@@ -219,20 +546,18 @@ impl Win {
         self.pixmap = conn.generate_id().expect("could not gen id");
         conn.composite_name_window_pixmap(window, self.pixmap)?
             .check()?;
-        unsafe { renderer.reacquire_glx_pixmap(self, display, config) }
-        Ok(())
+        backend.bind_pixmap(self, conn)
     }
-    pub fn release_pixmap(
+    pub fn release_pixmap<C: x11rb::connection::Connection>(
         &mut self,
-        display: *mut glx::types::Display,
-        renderer: &gl_renderer::GLRenderer,
+        conn: &C,
+        backend: &mut dyn renderer::Backend<C>,
     ) -> Result<(), errors::CompError> {
         // if not mapped, we already released the pixmaps/textures
         if !self.mapped {
             return Ok(());
         }
-        renderer.release_glx_pixmap(self, display);
-        Ok(())
+        backend.release_pixmap(self, conn)
     }
 }
 impl Drop for Win {
@@ -245,7 +570,29 @@ pub struct WinTracker {
     overlay: Window,
     wins: Vec<Win>,
 
-    region: Region,
+    pub config: config::Config,
+    atoms: ewm::Atoms,
+    pub monitors: Vec<monitor::Monitor>,
+    /// damaged area per monitor since the last frame, for frame pacing
+    damage_by_monitor: Vec<u64>,
+    pub pacer: pacing::FramePacer,
+
+    /// the window in _NET_ACTIVE_WINDOW, usually a client window rather than the frame we track
+    active: Option<Window>,
+    last_frame: Instant,
+
+    /// taken after the next frame is drawn
+    screenshots: Vec<screenshot::Request>,
+    recorder: Option<recorder::Recorder>,
+
+    pub stats: stats::FrameStats,
+    /// spent in process_and_render since the last frame
+    cpu_time: Duration,
+    /// everything that changed on screen since the last frame
+    frame_damage: damage::DamageRegion,
+    /// when each rectangle (in root coordinates) was damaged, oldest first,
+    /// only kept while debug-damage is on
+    recent_damage: VecDeque<(Instant, Rect)>,
 }
 
 impl WinTracker {
@@ -253,17 +600,31 @@ impl WinTracker {
         root: Window,
         overlay: Window,
         conn: &impl x11rb::connection::Connection,
+        config: config::Config,
     ) -> Result<WinTracker, errors::CompError> {
         let mut ret = WinTracker {
             root: root,
             overlay: overlay,
             wins: vec![Win::new_handle(root, conn, false)?],
 
-            region: conn.generate_id()?,
-        };
+            config,
+            atoms: ewm::Atoms::new(conn)?.reply()?,
+            monitors: monitor::query_monitors(conn, root)?,
+            damage_by_monitor: vec![],
+            pacer: pacing::FramePacer::new(),
+
+            active: None,
+            last_frame: Instant::now(),
+
+            screenshots: vec![],
+            recorder: None,
 
-        // reusable empty region for damage fetch requests
-        conn.xfixes_create_region(ret.region, &[])?.check()?;
+            stats: stats::FrameStats::default(),
+            cpu_time: Duration::ZERO,
+            frame_damage: damage::DamageRegion::default(),
+            recent_damage: VecDeque::new(),
+        };
+        ret.recorder = recorder::Recorder::new(&ret.config.record)?;
 
         let children = conn.query_tree(root)?.reply()?.children;
         for child in children {
@@ -271,20 +632,29 @@ impl WinTracker {
             ret.wins
                 .push(Win::new_handle(child, conn, child != overlay)?);
         }
+        let root_rect = ret.wins[0].rect;
+        for w in ret.wins.iter_mut().skip(1).filter(|w| w.mapped) {
+            w.update_props(conn, &ret.atoms, &ret.monitors, &root_rect)?;
+        }
+        ret.damage_by_monitor = vec![0; ret.monitors.len()];
+        ret.apply_rules();
+        ret.update_active(conn)?;
+        // don't fade in on startup
+        for w in ret.wins.iter_mut() {
+            w.dim = ret.config.dim_target(w, ret.active);
+        }
+        // nothing has been drawn yet
+        ret.damage_screen();
         Ok(ret)
     }
 
-    pub fn process_and_render(
+    pub fn process_and_render<C: x11rb::connection::Connection>(
         &mut self,
         event: &Option<Event>,
-        display: *mut glx::types::Display,
-        config: *const c_void,
-        conn: &impl x11rb::connection::Connection,
-        width: u16,
-        height: u16,
-        overlay: Window,
-        renderer: &gl_renderer::GLRenderer,
+        conn: &C,
+        backend: &mut dyn renderer::Backend<C>,
     ) -> Result<(), errors::CompError> {
+        let mut start = Instant::now();
         match event {
             Some(e) => {
                 println!("event: {:?}, num wins: {}", e, self.wins.len());
@@ -293,12 +663,29 @@ impl WinTracker {
                         self.wins.push(Win::new_event(&create, conn, true)?);
                     }
                     MapNotify(map) => {
+                        let root_rect = self.wins[0].rect;
                         let w = self
                             .wins
                             .iter_mut()
                             .find(|w| w.handle == map.window)
                             .ok_or("map notified with untracked window!".to_string())?;
-                        w.map(&map, display, config, conn, renderer)?;
+                        w.map(&map, conn, backend)?;
+                        w.update_props(conn, &self.atoms, &self.monitors, &root_rect)?;
+                        w.settings = self.config.settings_for(w);
+                        self.frame_damage.add(w.screen_rect());
+                    }
+                    // the root is the screen, don't want to treat it like a normal window
+                    ConfigureNotify(conf) if conf.window == self.root => {
+                        self.resize_screen(conn, backend)?;
+                    }
+                    RandrScreenChangeNotify(change) if change.root == self.root => {
+                        self.resize_screen(conn, backend)?;
+                    }
+                    RandrNotify(notify)
+                        if notify.sub_code == randr::Notify::CRTC_CHANGE
+                            || notify.sub_code == randr::Notify::OUTPUT_CHANGE =>
+                    {
+                        self.update_monitors(conn)?;
                     }
                     ConfigureNotify(conf) => {
                         let w = self
@@ -306,7 +693,7 @@ impl WinTracker {
                             .iter()
                             .position(|w| w.handle == conf.window)
                             .ok_or("configure notified with untracked window!".to_string())?;
-                        self.configure(w, &conf, display, config, conn, renderer)?;
+                        self.configure(w, &conf, conn, backend)?;
                     }
                     UnmapNotify(unmap) => {
                         let w = self
@@ -315,22 +702,42 @@ impl WinTracker {
                             .find(|w| w.handle == unmap.window)
                             .ok_or("unmap notified with untracked window!".to_string())?;
                         w.unmap(&unmap, conn)?;
+                        self.frame_damage.add(w.screen_rect());
                     }
                     DestroyNotify(destroy) => {
-                        self.wins
-                            .remove(
-                                self.wins
-                                    .iter()
-                                    .position(|w| w.handle == destroy.window)
-                                    .ok_or("destroy notify for untracked window".to_string())?,
-                            )
-                            .destroy(&destroy, display, conn, renderer)?;
+                        let mut w = self.wins.remove(
+                            self.wins
+                                .iter()
+                                .position(|w| w.handle == destroy.window)
+                                .ok_or("destroy notify for untracked window".to_string())?,
+                        );
+                        if w.mapped {
+                            self.frame_damage.add(w.screen_rect());
+                        }
+                        w.destroy(&destroy, conn, backend)?;
+                    }
+                    PropertyNotify(prop) if prop.window != self.root => {
+                        self.client_property_changed(prop.window, prop.atom, conn)?;
+                    }
+                    ShapeNotify(shape) if shape.shape_kind == shape::SK::BOUNDING => {
+                        if let Some(w) = self
+                            .wins
+                            .iter_mut()
+                            .find(|w| w.handle == shape.affected_window)
+                        {
+                            w.shaped = shape.shaped;
+                            w.settings = self.config.settings_for(w);
+                            if w.mapped {
+                                self.frame_damage.add(w.screen_rect());
+                            }
+                        }
                     }
                     PropertyNotify(prop) => match RootWindowHintCodes::try_from(prop.atom) {
                         Ok(RootWindowHintCodes::NetActiveWindow) => {
                             if prop.window != self.get_composite_win().handle {
                                 Err("root window atom's target was not root window".to_string())?;
                             }
+                            self.update_active(conn)?;
                         }
                         Ok(RootWindowHintCodes::NetClientListStacking) => {
                             if prop.window != self.get_composite_win().handle {
@@ -363,15 +770,13 @@ impl WinTracker {
                         ),
                     },
                     DamageNotify(damage) => {
-                        // sometimes damage is sent after things are cleaned up (i think?) so this throws an error
-                        // same thing in xcompmgr and picom src :/
-                        if conn
-                            .damage_subtract(damage.damage, 0 as u32, self.region)?
-                            .check()
-                            .is_ok()
-                        {
-                            let fetch = conn.xfixes_fetch_region(self.region)?.reply()?;
-                            println!("fetch: {:?}", fetch);
+                        // only marked here, the damage is fetched all at once when the frame is drawn
+                        if let Some(w) = self.wins.iter_mut().find(|w| w.damage == damage.damage) {
+                            w.damaged = true;
+                            if let Some(m) = w.monitor {
+                                self.damage_by_monitor[m] +=
+                                    damage.area.width as u64 * damage.area.height as u64;
+                            }
                         }
                     }
                     _ => println!("unhandled event!"),
@@ -379,28 +784,298 @@ impl WinTracker {
             }
             None => (),
         }
-        renderer.render(width, height, self, display, overlay, conn)?;
+        self.pacer
+            .pick_refresh_rate(self.config.pacing, &self.monitors, &self.damage_by_monitor);
+        if event.is_none() {
+            // nothing else to do until the next frame, which isn't counted as cpu time
+            self.cpu_time += start.elapsed();
+            self.pacer.wait_for_frame();
+            start = Instant::now();
+        }
+        if !self.pacer.frame_due() {
+            self.cpu_time += start.elapsed();
+            return Ok(());
+        }
+        self.update_dim();
+        while let Some((t, _)) = self.recent_damage.front() {
+            if t.elapsed() < DAMAGE_FADE_TIME {
+                break;
+            }
+            self.recent_damage.pop_front();
+        }
+        self.collect_damage(conn)?;
+        // the hud changes every frame and the damage tints fade out,
+        // otherwise the screen is left as it is until something changes
+        let redraw = !self.frame_damage.is_empty()
+            || self.config.effects.hud
+            || !self.recent_damage.is_empty();
+        if redraw {
+            backend.render(self, conn)?;
+            if let Some(r) = &mut self.recorder {
+                if let Err(e) = r.frame_presented(conn, backend) {
+                    println!("warning: recording failed, stopping: {:?}", e);
+                    self.recorder = None;
+                }
+            }
+        }
+        for req in std::mem::take(&mut self.screenshots) {
+            // a bad request shouldn't take the compositor down with it
+            match self.take_screenshot(&req, conn, backend) {
+                Ok(()) => println!("saved screenshot of {:?} to {}", req.target, req.path),
+                Err(e) => println!("warning: screenshot of {:?} failed: {:?}", req.target, e),
+            }
+        }
+        if !redraw {
+            self.pacer.frame_skipped();
+            self.cpu_time = Duration::ZERO;
+            return Ok(());
+        }
+        if let Some(path) = &self.config.dump_frame {
+            backend
+                .read_pixels(conn, &self.get_composite_win().rect)?
+                .write_pam(path)?;
+        }
+        self.pacer.frame_presented();
+        if let Some(passes) = backend.gpu_times() {
+            self.stats.gpu_frame(&passes);
+        }
+        self.stats
+            .frame_presented(self.cpu_time + start.elapsed(), self.frame_damage.area());
+        self.cpu_time = Duration::ZERO;
+        self.frame_damage.clear();
+        for d in self.damage_by_monitor.iter_mut() {
+            *d = 0;
+        }
         Ok(())
     }
 
+    /// empty the damage of every window that was notified about into the frame's damage
+    /// with DELTA_RECTANGLES nothing more is reported for an area until it's subtracted,
+    /// so everything has to be taken each frame, not just what the notifies covered
+    fn collect_damage(
+        &mut self,
+        conn: &impl x11rb::connection::Connection,
+    ) -> Result<(), errors::CompError> {
+        for w in self.wins.iter_mut() {
+            w.contents_changed = false;
+            w.damaged_rects.clear();
+        }
+        let damaged: Vec<usize> = (0..self.wins.len())
+            .filter(|&i| self.wins[i].damaged)
+            .collect();
+        // all the requests go out before waiting on any reply
+        let mut fetches = vec![];
+        for &i in damaged.iter() {
+            let w = &mut self.wins[i];
+            w.damaged = false;
+            // sometimes damage is sent after things are cleaned up (i think?) so this throws an error
+            // same thing in xcompmgr and picom src :/
+            // the region is emptied first so a failed subtract doesn't leave last frame's damage in it
+            conn.xfixes_set_region(w.damage_region, &[])?;
+            conn.damage_subtract(w.damage, 0 as u32, w.damage_region)?
+                .ignore_error();
+            fetches.push(conn.xfixes_fetch_region(w.damage_region)?);
+        }
+        let now = Instant::now();
+        for (i, fetch) in damaged.into_iter().zip(fetches) {
+            let rects = fetch.reply()?.rectangles;
+            let w = &mut self.wins[i];
+            w.contents_changed = !rects.is_empty();
+            let border = w.border_width as i16;
+            w.damaged_rects = rects
+                .iter()
+                .map(|r| Rect::new(r.x + border, r.y + border, r.width, r.height))
+                .collect();
+            if !w.mapped {
+                continue;
+            }
+            for r in rects.iter() {
+                let r = w.damage_to_screen(r);
+                self.frame_damage.add(r);
+                if self.config.effects.debug_damage {
+                    self.recent_damage.push_back((now, r));
+                }
+            }
+        }
+        // anything changing behind a blurred window, or near enough for the blur to sample it,
+        // changes all of its blurred background
+        let margin = self.config.blur.margin();
+        let root = self.wins[0].rect;
+        for w in self
+            .wins
+            .iter()
+            .skip(1)
+            .filter(|w| w.mapped && w.settings.blur)
+        {
+            let rect = w.screen_rect();
+            if let Some(sampled) = rect.grow(margin, &root) {
+                if self.frame_damage.intersects(&sampled) {
+                    self.frame_damage.add(rect);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// repaint everything next frame, e.g. after a setting that affects every window changed
+    pub fn damage_screen(&mut self) {
+        let root = self.get_composite_win().rect;
+        self.frame_damage.add(root);
+    }
+
+    /// what's changed on screen since the last frame
+    pub fn frame_damage(&self) -> &damage::DamageRegion {
+        &self.frame_damage
+    }
+
+    /// repaint `rect` next frame, e.g. after a window's opacity changed
+    pub fn damage_rect(&mut self, rect: Rect) {
+        self.frame_damage.add(rect);
+    }
+
+    pub fn request_screenshot(&mut self, req: screenshot::Request) {
+        self.screenshots.push(req);
+    }
+
+    fn take_screenshot<C: x11rb::connection::Connection>(
+        &self,
+        req: &screenshot::Request,
+        conn: &C,
+        backend: &mut dyn renderer::Backend<C>,
+    ) -> Result<(), errors::CompError> {
+        let rect = match req.target {
+            screenshot::Target::Screen => self.get_composite_win().rect,
+            screenshot::Target::Monitor(i) => {
+                self.monitors
+                    .get(i)
+                    .ok_or(format!("no monitor {}", i))?
+                    .rect
+            }
+            screenshot::Target::Window(handle) => {
+                self.mapped_wins()
+                    .find(|w| w.handle == handle || w.client == Some(handle))
+                    .ok_or(format!("no mapped window {:#x}", handle))?
+                    .rect
+            }
+        };
+        screenshot::write_png(&backend.read_pixels(conn, &rect)?, &req.path)
+    }
+
+    /// the root changed size (new resolution, monitor plugged in, etc.)
+    /// we get both a ConfigureNotify and a ScreenChangeNotify, so this is a noop if nothing changed
+    pub fn resize_screen<C: x11rb::connection::Connection>(
+        &mut self,
+        conn: &C,
+        backend: &mut dyn renderer::Backend<C>,
+    ) -> Result<(), errors::CompError> {
+        let geom = conn.get_geometry(self.root)?.reply()?;
+        let root = &mut self.wins[0];
+        if root.rect.width == geom.width && root.rect.height == geom.height {
+            return Ok(());
+        }
+        println!("screen resized to {}x{}", geom.width, geom.height);
+        root.rect = Rect::new(geom.x, geom.y, geom.width, geom.height);
+        // everything is drawn to the overlay, so it has to cover the new root before
+        // the screen sized resources are made to match it
+        let overlay = conn.get_geometry(self.overlay)?.reply()?;
+        if overlay.width != geom.width || overlay.height != geom.height {
+            conn.configure_window(
+                self.overlay,
+                &ConfigureWindowAux::new()
+                    .x(0)
+                    .y(0)
+                    .width(u32::from(geom.width))
+                    .height(u32::from(geom.height)),
+            )?;
+        }
+        backend.resize(conn, geom.width, geom.height)?;
+
+        self.damage_screen();
+        self.update_monitors(conn)
+    }
+
+    /// requery the crtc layout, which also changes what counts as fullscreen
+    pub fn update_monitors(
+        &mut self,
+        conn: &impl x11rb::connection::Connection,
+    ) -> Result<(), errors::CompError> {
+        self.monitors = monitor::query_monitors(conn, self.root)?;
+        self.damage_by_monitor = vec![0; self.monitors.len()];
+        let root_rect = self.wins[0].rect;
+        for w in self.wins.iter_mut().skip(1) {
+            w.update_monitor(&self.monitors, &root_rect);
+            w.settings = self.config.settings_for(w);
+        }
+        Ok(())
+    }
+
+    pub fn update_active(
+        &mut self,
+        conn: &impl x11rb::connection::Connection,
+    ) -> Result<(), errors::CompError> {
+        let res = conn
+            .get_property(
+                false,
+                self.get_composite_win().handle,
+                RootWindowHintCodes::NetActiveWindow as u32,
+                AtomEnum::WINDOW,
+                0,
+                1,
+            )?
+            .reply()?;
+        self.active = match res.value32().and_then(|mut v| v.next()) {
+            Some(0) | None => None,
+            Some(w) => Some(w),
+        };
+        Ok(())
+    }
+
+    /// fade each window's dim towards what it should be for the current active window
+    fn update_dim(&mut self) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_frame).as_secs_f32();
+        self.last_frame = now;
+
+        // not scaled by the amount, windows that are still dimmed have to fade out when it's set to 0
+        let max_step = self.config.dim.max_step(elapsed);
+        for w in self.wins.iter_mut() {
+            let target = self.config.dim_target(w, self.active);
+            let dim = w.dim;
+            w.step_dim(target, max_step);
+            if w.dim != dim && w.mapped {
+                self.frame_damage.add(w.screen_rect());
+            }
+        }
+    }
+
     // TODO: handle all configure notify possibilities (stacking order, etc.)
-    pub fn configure(
+    pub fn configure<C: x11rb::connection::Connection>(
         &mut self,
         win_pos: usize,
         evt: &ConfigureNotifyEvent,
-        display: *mut glx::types::Display,
-        config: *const c_void,
-        conn: &impl x11rb::connection::Connection,
-        renderer: &gl_renderer::GLRenderer,
+        conn: &C,
+        backend: &mut dyn renderer::Backend<C>,
     ) -> Result<(), errors::CompError> {
+        let root_rect = self.wins[0].rect;
         let win = &mut self.wins[win_pos];
+        let mapped = win.mapped;
+        if mapped {
+            self.frame_damage.add(win.screen_rect());
+        }
         win.rect.x = evt.x;
         win.rect.y = evt.y;
+        win.border_width = evt.border_width;
         if win.rect.width != evt.width || win.rect.height != evt.height {
-            win.reacquire_pixmap(evt.window, display, config, conn, renderer)?;
+            win.reacquire_pixmap(evt.window, conn, backend)?;
             win.rect.width = evt.width;
             win.rect.height = evt.height;
         }
+        if mapped {
+            self.frame_damage.add(win.screen_rect());
+        }
+        // going fullscreen is usually just a resize to the monitor size
+        win.update_monitor(&self.monitors, &root_rect);
+        win.settings = self.config.settings_for(win);
 
         if (win_pos == 0 && evt.above_sibling != 0)
             || (win_pos > 0 && self.wins[win_pos - 1].handle != evt.above_sibling)
@@ -424,6 +1099,44 @@ impl WinTracker {
         Ok(())
     }
 
+    /// a property changed on a client, which the config rules or occlusion might depend on
+    fn client_property_changed(
+        &mut self,
+        client: Window,
+        atom: x11rb::protocol::xproto::Atom,
+        conn: &impl x11rb::connection::Connection,
+    ) -> Result<(), errors::CompError> {
+        let atoms = &self.atoms;
+        if ![
+            atoms._NET_WM_STATE,
+            atoms._NET_WM_WINDOW_TYPE,
+            atoms._NET_WM_OPAQUE_REGION,
+        ]
+        .contains(&atom)
+        {
+            return Ok(());
+        }
+        let root_rect = self.wins[0].rect;
+        if let Some(w) = self
+            .wins
+            .iter_mut()
+            .find(|w| w.mapped && w.client == Some(client))
+        {
+            w.update_props(conn, &self.atoms, &self.monitors, &root_rect)?;
+            w.settings = self.config.settings_for(w);
+            self.frame_damage.add(w.screen_rect());
+        }
+        Ok(())
+    }
+
+    /// re-evaluate the config rules for every window, e.g. after the config changed
+    pub fn apply_rules(&mut self) {
+        for w in self.wins.iter_mut() {
+            w.settings = self.config.settings_for(w);
+        }
+        self.damage_screen();
+    }
+
     pub fn get_composite_win(&self) -> &Win {
         &self.wins[0]
     }
@@ -431,4 +1144,94 @@ impl WinTracker {
     pub fn mapped_wins(&self) -> impl Iterator<Item = &Win> {
         self.wins.iter().filter(|w| w.mapped).into_iter()
     }
+
+    /// the window in _NET_ACTIVE_WINDOW, usually a client rather than a frame
+    pub fn active(&self) -> Option<Window> {
+        self.active
+    }
+
+    /// what can be seen of each window that's drawn, bottom to top, after cutting out
+    /// the opaque parts of the windows above it, windows that are completely covered are left out
+    /// the rectangles of each region don't overlap
+    pub fn unoccluded_wins(&self) -> Vec<(&Win, damage::DamageRegion)> {
+        let mut ret = vec![];
+        let mut occluders: Vec<Rect> = vec![];
+        for w in self
+            .wins
+            .iter()
+            .rev()
+            .filter(|w| w.mapped && w.track_damage)
+        {
+            let mut visible = damage::DamageRegion::default();
+            visible.add(w.screen_rect());
+            for o in occluders.iter() {
+                visible.subtract(o);
+                if visible.is_empty() {
+                    break;
+                }
+            }
+            if !visible.is_empty() {
+                ret.push((w, visible));
+            }
+            occluders.extend(w.opaque_rects(self.config.corner_radius));
+        }
+        ret.reverse();
+        ret
+    }
+
+    /// recently damaged rectangles and how far they've faded, from 0 (just damaged) to 1
+    pub fn recent_damage(&self) -> impl Iterator<Item = (f32, &Rect)> {
+        self.recent_damage.iter().map(|(t, r)| {
+            (
+                (t.elapsed().as_secs_f32() / DAMAGE_FADE_TIME.as_secs_f32()).min(1.0),
+                r,
+            )
+        })
+    }
+
+    /// every tracked window bottom to top, the root first
+    pub fn wins(&self) -> &[Win] {
+        &self.wins
+    }
+
+    /// `handle` can be either the frame we track or its client
+    pub fn find_win_mut(&mut self, handle: Window) -> Option<&mut Win> {
+        self.wins
+            .iter_mut()
+            .find(|w| w.handle == handle || w.client == Some(handle))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn grow_clips_without_overflowing() {
+        let screen = Rect::new(0, 0, 1920, 1080);
+        let r = Rect::new(10, 10, 100, 100);
+        assert_eq!(r.grow(5, &screen), Some(Rect::new(5, 5, 110, 110)));
+        assert_eq!(r.grow(50, &screen), Some(Rect::new(0, 0, 160, 160)));
+        let far = Rect::new(i16::MIN, i16::MIN, u16::MAX, u16::MAX);
+        assert_eq!(far.grow(100, &screen), Some(Rect::new(0, 0, 1920, 1080)));
+        assert_eq!(Rect::new(-500, 0, 10, 10).grow(100, &screen), None);
+    }
+
+    #[test]
+    fn dim_fades_out_when_the_amount_drops_to_zero() {
+        let mut dim = config::DimConfig {
+            amount: 0.2,
+            fade_time: 0.75,
+        };
+        let mut w = Win::without_damage(1, Rect::new(0, 0, 10, 10), 0, false, 0, 24, true);
+        for _ in 0..30 {
+            w.step_dim(dim.amount, dim.max_step(0.016));
+        }
+        assert_eq!(w.dim, 0.2);
+        dim.amount = 0.0;
+        for _ in 0..30 {
+            w.step_dim(dim.amount, dim.max_step(0.016));
+        }
+        assert_eq!(w.dim, 0.0);
+    }
 }
@@ -0,0 +1,305 @@
+use crate::errors;
+use crate::renderer;
+use crate::win;
+
+use std::collections::HashMap;
+
+use x11rb::protocol::render::{
+    Color, ConnectionExt as render_ConnectionExt, CreatePictureAux, PictOp, Pictformat, Picture,
+    QueryPictFormatsReply,
+};
+use x11rb::protocol::xproto::{
+    ConnectionExt as xproto_ConnectionExt, ImageFormat, Pixmap, Visualid, Window,
+};
+
+/// software compositing with the XRender extension, for when there's no usable GL
+/// doesn't do any of the shader effects (blur, rounded corners), only stacking, alpha and dimming
+#[derive(Debug)]
+pub struct XRenderRenderer {
+    formats: QueryPictFormatsReply,
+
+    root: Window,
+    depth: u8,
+    /// format of the root visual, used for the overlay and the back buffer
+    format: Pictformat,
+
+    overlay_picture: Picture,
+    /// everything is drawn here first then copied to the overlay in one go, so there's no flicker
+    back_pixmap: Pixmap,
+    back_picture: Picture,
+
+    width: u16,
+    height: u16,
+    /// leave finished frames in the back buffer instead of copying them to the overlay
+    headless: bool,
+
+    /// picture of each window's backing pixmap
+    pictures: HashMap<Window, Picture>,
+}
+
+impl XRenderRenderer {
+    pub fn new(
+        conn: &impl x11rb::connection::Connection,
+        screen_num: usize,
+        overlay: Window,
+        width: u16,
+        height: u16,
+        headless: bool,
+    ) -> Result<XRenderRenderer, errors::CompError> {
+        let screen = &conn.setup().roots[screen_num];
+        let formats = conn.render_query_pict_formats()?.reply()?;
+        let format = find_visual_format(&formats, screen.root_visual)
+            .ok_or("no picture format for the root visual".to_string())?;
+        let mut ret = XRenderRenderer {
+            formats,
+
+            root: screen.root,
+            depth: screen.root_depth,
+            format,
+
+            overlay_picture: conn.generate_id()?,
+            back_pixmap: 0,
+            back_picture: 0,
+
+            width,
+            height,
+            headless,
+
+            pictures: HashMap::new(),
+        };
+        conn.render_create_picture(
+            ret.overlay_picture,
+            overlay,
+            ret.format,
+            &CreatePictureAux::new(),
+        )?
+        .check()?;
+        ret.create_back_buffer(conn)?;
+        Ok(ret)
+    }
+
+    fn create_back_buffer(
+        &mut self,
+        conn: &impl x11rb::connection::Connection,
+    ) -> Result<(), errors::CompError> {
+        self.back_pixmap = conn.generate_id()?;
+        conn.create_pixmap(
+            self.depth,
+            self.back_pixmap,
+            self.root,
+            self.width,
+            self.height,
+        )?
+        .check()?;
+        self.back_picture = conn.generate_id()?;
+        conn.render_create_picture(
+            self.back_picture,
+            self.back_pixmap,
+            self.format,
+            &CreatePictureAux::new(),
+        )?
+        .check()?;
+        Ok(())
+    }
+
+    fn render_frame(
+        &self,
+        wins: &win::WinTracker,
+        conn: &impl x11rb::connection::Connection,
+    ) -> Result<(), errors::CompError> {
+        let black = Color {
+            red: 0,
+            green: 0,
+            blue: 0,
+            alpha: 0xffff,
+        };
+        conn.render_fill_rectangles(
+            PictOp::SRC,
+            self.back_picture,
+            black,
+            &[x11rb::protocol::xproto::Rectangle {
+                x: 0,
+                y: 0,
+                width: self.width,
+                height: self.height,
+            }],
+        )?;
+
+        for (w, visible) in wins.unoccluded_wins() {
+            let picture = match self.pictures.get(&w.handle()) {
+                Some(&p) => p,
+                None => continue,
+            };
+            // without an alpha channel the alpha is garbage, so just copy
+            let op = match w.argb || w.opacity < 1.0 {
+                true => PictOp::OVER,
+                false => PictOp::SRC,
+            };
+            // a translucent window is drawn through a solid alpha mask
+            let mask = match w.opacity < 1.0 {
+                true => {
+                    let mask = conn.generate_id()?;
+                    conn.render_create_solid_fill(
+                        mask,
+                        Color {
+                            alpha: (w.opacity.max(0.0) * 0xffff as f32) as u16,
+                            ..black
+                        },
+                    )?;
+                    mask
+                }
+                false => x11rb::NONE,
+            };
+            // only the parts that aren't covered up, which don't overlap
+            for r in visible.rects() {
+                conn.render_composite(
+                    op,
+                    picture,
+                    mask,
+                    self.back_picture,
+                    r.x - w.rect.x,
+                    r.y - w.rect.y,
+                    0,
+                    0,
+                    r.x,
+                    r.y,
+                    r.width,
+                    r.height,
+                )?;
+            }
+            if mask != x11rb::NONE {
+                conn.render_free_picture(mask)?;
+            }
+            if w.dim > 0.0 {
+                let shade = conn.generate_id()?;
+                conn.render_create_solid_fill(
+                    shade,
+                    Color {
+                        alpha: (w.dim.min(1.0) * w.opacity.clamp(0.0, 1.0) * 0xffff as f32) as u16,
+                        ..black
+                    },
+                )?;
+                for r in visible.rects() {
+                    conn.render_composite(
+                        PictOp::OVER,
+                        shade,
+                        x11rb::NONE,
+                        self.back_picture,
+                        0,
+                        0,
+                        0,
+                        0,
+                        r.x,
+                        r.y,
+                        r.width,
+                        r.height,
+                    )?;
+                }
+                conn.render_free_picture(shade)?;
+            }
+        }
+
+        if !self.headless {
+            conn.render_composite(
+                PictOp::SRC,
+                self.back_picture,
+                x11rb::NONE,
+                self.overlay_picture,
+                0,
+                0,
+                0,
+                0,
+                0,
+                0,
+                self.width,
+                self.height,
+            )?;
+        }
+        conn.flush()?;
+        Ok(())
+    }
+}
+
+impl<C: x11rb::connection::Connection> renderer::Backend<C> for XRenderRenderer {
+    fn name(&self) -> &'static str {
+        "xrender"
+    }
+    fn bind_pixmap(&mut self, win: &win::Win, conn: &C) -> Result<(), errors::CompError> {
+        renderer::Backend::release_pixmap(self, win, conn)?;
+        let format = find_visual_format(&self.formats, win.visual)
+            .ok_or(format!("no picture format for visual {}", win.visual))?;
+        let picture = conn.generate_id()?;
+        conn.render_create_picture(picture, win.pixmap, format, &CreatePictureAux::new())?
+            .check()?;
+        self.pictures.insert(win.handle(), picture);
+        Ok(())
+    }
+    fn release_pixmap(&mut self, win: &win::Win, conn: &C) -> Result<(), errors::CompError> {
+        if let Some(picture) = self.pictures.remove(&win.handle()) {
+            conn.render_free_picture(picture)?;
+        }
+        Ok(())
+    }
+    fn resize(&mut self, conn: &C, width: u16, height: u16) -> Result<(), errors::CompError> {
+        conn.render_free_picture(self.back_picture)?;
+        conn.free_pixmap(self.back_pixmap)?;
+        self.width = width;
+        self.height = height;
+        self.create_back_buffer(conn)
+    }
+    fn render(&mut self, wins: &win::WinTracker, conn: &C) -> Result<(), errors::CompError> {
+        self.render_frame(wins, conn)
+    }
+    /// there's nothing to overlap the readback with here, so this just reads the frame straight away
+    fn read_frame_async(&mut self, conn: &C) -> Result<Option<renderer::Frame>, errors::CompError> {
+        let screen = win::Rect::new(0, 0, self.width, self.height);
+        renderer::Backend::read_pixels(self, conn, &screen).map(Some)
+    }
+    fn read_pixels(
+        &mut self,
+        conn: &C,
+        rect: &win::Rect,
+    ) -> Result<renderer::Frame, errors::CompError> {
+        let rect = rect
+            .intersect(&win::Rect::new(0, 0, self.width, self.height))
+            .ok_or(format!("{:?} is outside the screen", rect))?;
+        let image = conn
+            .get_image(
+                ImageFormat::Z_PIXMAP,
+                self.back_pixmap,
+                rect.x,
+                rect.y,
+                rect.width,
+                rect.height,
+                !0,
+            )?
+            .reply()?;
+        if image.data.len() != rect.width as usize * rect.height as usize * 4 {
+            Err(format!(
+                "expected 32 bits per pixel reading back depth {}",
+                image.depth
+            ))?
+        }
+        // the server gives us BGRX (on little endian at least)
+        let data = image
+            .data
+            .chunks_exact(4)
+            .flat_map(|p| [p[2], p[1], p[0], 0xff])
+            .collect();
+        Ok(renderer::Frame {
+            width: rect.width,
+            height: rect.height,
+            data,
+        })
+    }
+}
+
+fn find_visual_format(formats: &QueryPictFormatsReply, visual: Visualid) -> Option<Pictformat> {
+    formats
+        .screens
+        .iter()
+        .flat_map(|s| s.depths.iter())
+        .flat_map(|d| d.visuals.iter())
+        .find(|v| v.visual == visual)
+        .map(|v| v.format)
+}